@@ -1,166 +1,231 @@
-//! This module defines the application state and fiel handling logic.
+//! This module defines the application state and file handling logic.
 
 // Ergonomic Result and Error types to simply error handling boilerplate
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 
 // DateTime handling
 use chrono::prelude::{DateTime, Utc};
 
-// For parsing/serializing file permissions
-use libc::{S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP, S_IXOTH, S_IXUSR};
+// Background jobs report through the event channel, which is keyed on termion keys
+use termion::event::Key;
 
-use crate::stateful_list::StatefulList;
+use crate::{
+    events::Event,
+    filter::{Filter, MatchKind},
+    finder::Finder,
+    fs_ops::{
+        birth_time, copy_path, make_dir, move_path, normalize, relative_path, touch, CopyOptions,
+        NoProgress, Progress,
+    },
+    history::{History, Visit},
+    jobs::{JobEvent, JobState, Jobs, Sizing},
+    messages::{Level, MessageLog},
+    permissions::{
+        describe_mode, mode_warning, ChangeSummary, ModeChange, ModeSpec, PermissionEditor,
+    },
+    preview::{parse_offset, PreviewState, Previewer},
+    query::{Query, QueryEvent, QueryResults},
+    search::{Search, SearchEvent, SearchOptions},
+    sort::SortOrder,
+    stateful_list::StatefulList,
+    trash::{Trash, TrashEntry},
+    users::Users,
+};
 
 // Input and output (stdio, stderr, etc), OS integration, type conversions
 use std::{
+    collections::{BTreeSet, HashMap},
     fmt,
-    fs::{self, canonicalize, File},
-    io::{prelude::*, BufReader},
-    os::unix::fs::PermissionsExt, // Unix-specific st_mode
+    fs::{self, canonicalize},
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt}, // Unix-specific stat fields
     path::{Path, PathBuf},
     str::FromStr,
-    time::SystemTime,
+    sync::mpsc::Sender,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-/// Pretty-print a permissions triplet into a human-readable string component
-fn triplet(mode: u16, read: u16, write: u16, execute: u16) -> String {
-    match (mode & read, mode & write, mode & execute) {
-        (0, 0, 0) => "---",
-        (_, 0, 0) => "r--",
-        (0, _, 0) => "-w-",
-        (0, 0, _) => "--x",
-        (_, 0, _) => "r-x",
-        (_, _, 0) => "rw-",
-        (0, _, _) => "-wx",
-        (_, _, _) => "rwx",
-    }
-    .to_string()
+/// Helper function to unwrap times which may not come back.
+fn unwrap_time(r: Option<SystemTime>) -> String {
+    if let Some(ts) = r {
+        // Convert to a readable timestamp
+        let dt: DateTime<Utc> = ts.into();
+        format!("{}", dt.format("%+"))
+    } else {
+        "unknown".to_string()
+    }
 }
 
-/// Parse an st_mode bitfield into a human-readable permission string
-fn permissions_to_string(mode: u16) -> String {
-    let user = triplet(mode, S_IRUSR as u16, S_IWUSR as u16, S_IXUSR as u16);
-    let group = triplet(mode, S_IRGRP as u16, S_IWGRP as u16, S_IXGRP as u16);
-    let other = triplet(mode, S_IROTH as u16, S_IWOTH as u16, S_IXOTH as u16);
-    [user, group, other].join("")
+/// Split a device number into its major and minor parts
+fn device_numbers(dev: u64) -> (u32, u32) {
+    (libc::major(dev), libc::minor(dev))
 }
 
-/// Get the correct u32 mode value from a rwxrwxrwx permission string
-fn string_to_permissions(s: &str) -> Option<u16> {
-    if s.len() == 9 {
-        Some(s.chars().collect::<Vec<char>>().chunks(3).enumerate().fold(
-            0,
-            |mut acc, (idx, triple)| match idx {
-                0 => {
-                    if triple[0] == 'r' {
-                        acc |= S_IRUSR as u16;
-                    }
-                    if triple[1] == 'w' {
-                        acc |= S_IWUSR as u16;
-                    }
-                    if triple[2] == 'x' {
-                        acc |= S_IXUSR as u16;
-                    }
-                    acc
-                }
-                1 => {
-                    if triple[0] == 'r' {
-                        acc |= S_IRGRP as u16;
-                    }
-                    if triple[1] == 'w' {
-                        acc |= S_IWGRP as u16;
-                    }
-                    if triple[2] == 'x' {
-                        acc |= S_IXGRP as u16;
-                    }
-                    acc
-                }
-                2 => {
-                    if triple[0] == 'r' {
-                        acc |= S_IROTH as u16;
-                    }
-                    if triple[1] == 'w' {
-                        acc |= S_IWOTH as u16;
-                    }
-                    if triple[2] == 'x' {
-                        acc |= S_IXOTH as u16;
-                    }
-                    acc
-                }
-                _ => unreachable!(),
-            },
-        ))
+/// What kind of entry a file type describes, in the words `stat` uses
+fn file_type_name(file_type: &fs::FileType) -> &'static str {
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "regular file"
+    } else if file_type.is_symlink() {
+        "symbolic link"
+    } else if file_type.is_fifo() {
+        "FIFO"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else if file_type.is_block_device() {
+        "block device"
     } else {
-        None
+        "file of unknown type"
     }
 }
 
-/// Helper function to unwrap times which may not come back.
-fn unwrap_time(r: std::result::Result<SystemTime, std::io::Error>) -> String {
-    if let Ok(ts) = r {
-        // Convert to a readable timestamp
-        let dt: DateTime<Utc> = ts.into();
-        format!("{}", dt.format("%+"))
+/// Format a byte count with binary prefixes, e.g. `1.5 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
     } else {
-        "never".to_string()
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Whether a symbolic link leads anywhere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkState {
+    Resolves,
+    /// The target doesn't exist
+    Broken,
+    /// Following the link leads back to itself
+    Loop,
+}
+
+/// Where a symbolic link points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// The target as stored in the link, possibly relative to the link's directory
+    pub target: PathBuf,
+    pub state: LinkState,
+}
+
+impl Link {
+    /// Read the link at `path`, or `None` if it isn't one
+    fn read(path: &Path) -> Option<Self> {
+        let target = fs::read_link(path).ok()?;
+        let state = match fs::metadata(path) {
+            Ok(_) => LinkState::Resolves,
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => LinkState::Loop,
+            Err(_) => LinkState::Broken,
+        };
+        Some(Self { target, state })
+    }
+
+    /// A note for links that don't lead anywhere
+    pub fn problem(&self) -> Option<&'static str> {
+        match self.state {
+            LinkState::Resolves => None,
+            LinkState::Broken => Some("broken"),
+            LinkState::Loop => Some("loop"),
+        }
     }
 }
 
 /// Each displayed entry stores some information about itself
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileListing {
     path: PathBuf,
+    /// A directory, or a link to one, so it can be entered
     pub is_directory: bool,
+    /// Set when the entry itself is a symbolic link
+    pub link: Option<Link>,
 }
 
 impl FileListing {
     // Constructor
     pub fn new(path: PathBuf, is_directory: bool) -> Self {
-        Self { path, is_directory }
+        Self {
+            path,
+            is_directory,
+            link: None,
+        }
     }
 
-    /// Get the file contents as a string
-    pub fn contents(&self) -> Result<Option<String>> {
-        if self.is_directory {
-            Ok(None)
-        } else {
-            // Open the file, store the descriptor
-            let f = File::open(&self.path)?;
-            // Initialize a buffered reader
-            let mut buf_reader = BufReader::new(f);
-            // Initialize return string
-            let mut result = String::new();
-            // Read entire file to result string
-            buf_reader.read_to_string(&mut result)?;
-            // Return result
-            Ok(Some(result))
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Look up what kind of entry `path` is, without failing on broken links
+    pub fn read(path: PathBuf) -> Self {
+        Self {
+            is_directory: path.is_dir(),
+            link: Link::read(&path),
+            path,
         }
     }
 
     /// Returns a multi-line string to render in the detail tab when the file is selected.
-    pub fn detail_string(&self) -> Result<String> {
-        // Read the metadata
-        let m = fs::metadata(&self.path)?;
-
-        let d_or_f = if m.is_dir() {
-            "directory"
-        } else {
-            "regular file"
-        };
-        let len = m.len(); // in bytes
-
-        let permissions = permissions_to_string(m.permissions().mode() as u16);
+    pub fn detail_string(&self, users: &Users) -> Result<String> {
+        // Read the metadata of the entry itself, not what a link points to
+        let m = fs::symlink_metadata(&self.path)?;
+        let file_type = m.file_type();
 
-        let last_modified = unwrap_time(m.modified());
-        let last_accessed = unwrap_time(m.accessed());
-        //let created = unwrap_time(m.created()); // FIXME
+        let mut lines = vec![format!(
+            "Path {:?} is a {}.",
+            self.path,
+            file_type_name(&file_type)
+        )];
+        if let Some(link) = &self.link {
+            let mut target = format!("Target: {:?}", link.target);
+            if let Some(problem) = link.problem() {
+                target.push_str(&format!(" ({})", problem));
+            }
+            lines.push(target);
+        }
+        lines.push(format!("Size: {} bytes ({})", m.len(), human_size(m.len())));
+        // st_blocks always counts 512-byte units
+        lines.push(format!(
+            "Allocated: {} blocks ({}), I/O block size {}",
+            m.blocks(),
+            human_size(m.blocks() * 512),
+            m.blksize()
+        ));
+        let (major, minor) = device_numbers(m.dev());
+        lines.push(format!(
+            "Device: {}:{} Inode: {} Links: {}",
+            major,
+            minor,
+            m.ino(),
+            m.nlink()
+        ));
+        if file_type.is_char_device() || file_type.is_block_device() {
+            let (major, minor) = device_numbers(m.rdev());
+            lines.push(format!("Device type: {}:{}", major, minor));
+        }
+        lines.push(format!(
+            "Permissions: {}",
+            describe_mode(m.permissions().mode())
+        ));
+        lines.push(format!(
+            "Owner: {} Group: {}",
+            users.describe_user(m.uid()),
+            users.describe_group(m.gid())
+        ));
 
-        let result = format!("Path {:?} is a {}.\nSize: {} bytes\nLast modified: {}\nLast accessed: {}\nPermissions: {}",
-        self.path, d_or_f, len, last_accessed, last_modified, permissions
-    );
+        let changed =
+            UNIX_EPOCH.checked_add(Duration::new(m.ctime() as u64, m.ctime_nsec() as u32));
+        lines.push(format!("Last accessed: {}", unwrap_time(m.accessed().ok())));
+        lines.push(format!("Last modified: {}", unwrap_time(m.modified().ok())));
+        lines.push(format!("Last changed: {}", unwrap_time(changed)));
+        lines.push(format!("Created: {}", unwrap_time(birth_time(&self.path))));
 
-        Ok(result)
-        // Rust automatically closes the file at the end of the block here
+        Ok(lines.join("\n"))
     }
 }
 
@@ -175,8 +240,8 @@ impl fmt::Display for FileListing {
     }
 }
 
-/// Return a StatefulList containing all files in the given directory/
-fn list_of_dir(path: &Path) -> Result<StatefulList<(FileListing, usize)>> {
+/// Return the files in the given directory, in the given order after "." and ".."
+fn list_of_dir(path: &Path, order: &SortOrder) -> Result<Vec<(FileListing, usize)>> {
     // This only makes sense if the path is a directory
     let result: Vec<(FileListing, usize)> = if path.is_dir() {
         let mut vec = Vec::new();
@@ -184,34 +249,60 @@ fn list_of_dir(path: &Path) -> Result<StatefulList<(FileListing, usize)>> {
         let parent = canonicalized
             .as_path()
             .parent()
-            .unwrap_or_else(|| canonicalized.as_path());
+            .unwrap_or(canonicalized.as_path());
 
         // First, we'll always push an entry for "." and ".."
         vec.push((FileListing::new(canonicalized.clone(), true), 0));
         vec.push((FileListing::new(parent.to_path_buf(), true), 1));
 
+        let mut entries = Vec::new();
         for (idx, entry) in fs::read_dir(path)?.enumerate() {
             // Unwrap entry
             let entry = entry?;
-            // Grab path and check if its a directory
-            let path = entry.path();
-            let is_dir = path.is_dir();
-            // Add the result to the return vector
-            vec.push((FileListing::new(path, is_dir), idx + 2))
+            // Add the result to the return vector, noting whether it's a directory or a link
+            entries.push((FileListing::read(entry.path()), idx + 2));
         }
+        order.sort(&mut entries);
+        vec.append(&mut entries);
         vec
     } else {
         vec![]
     };
 
-    Ok(StatefulList::with_items(result))
+    Ok(result)
+}
+
+/// Dotfiles are hidden unless asked for
+fn is_hidden(listing: &FileListing) -> bool {
+    listing.to_string().starts_with('.')
+}
+
+/// Split `path -m MODE`, as typed when creating an entry, into the path and the optional mode
+fn split_mode(input: &str) -> Result<(&str, Option<ModeSpec>)> {
+    let (path, mode) = match input.rsplit_once(" -m ") {
+        Some((path, mode)) => (path, Some(mode.parse()?)),
+        None => (input, None),
+    };
+    if path.is_empty() {
+        bail!("Enter a path");
+    }
+    Ok((path, mode))
 }
 
-/// The application has a user input secondary mode
-#[derive(Debug, PartialEq)]
+/// The application has a user input secondary mode, a view of the trash, and a view of the message log
+#[derive(Debug, Default, PartialEq)]
 pub enum AppMode {
+    #[default]
     Nav,
     Input(InputType),
+    Trash,
+    Log,
+    /// The permission editor is open over the directory view
+    PermissionEditor,
+    /// The fuzzy finder is open over the directory view
+    Finder,
+    /// Content search results take the place of the directory listing
+    Search,
 }
 
 /// There are several possible input types
@@ -219,7 +310,31 @@ pub enum AppMode {
 pub enum InputType {
     Permission,
     CopyFile,
+    MoveFile,
     ChangeDir,
+    GotoOffset,
+    /// Permissions for a whole tree
+    RecursivePermission,
+    /// Owner and group, optionally for a whole tree
+    Ownership {
+        recursive: bool,
+    },
+    /// Where to put a symbolic link to the selected entry
+    Symlink {
+        relative: bool,
+    },
+    /// Where to put a hard link to the selected entry
+    HardLink,
+    NewFile,
+    NewDirectory,
+    /// A pattern narrowing the listing, applied as it is typed
+    Filter {
+        kind: MatchKind,
+    },
+    /// What to search file contents for
+    Search(SearchOptions),
+    /// Which files to find by their metadata
+    Query,
 }
 
 impl InputType {
@@ -228,70 +343,998 @@ impl InputType {
         match self {
             InputType::ChangeDir => "Enter destination directory",
             InputType::CopyFile => "Enter target",
-            InputType::Permission => "Enter permission string from --------- to rwxrwxrwx",
+            InputType::MoveFile => "Enter new name or destination",
+            InputType::Permission => {
+                "Enter mode: octal (0644), symbolic (u+x,go-w) or rw-r--r--, then :directory mode if different"
+            }
+            InputType::GotoOffset => "Enter byte offset, decimal or 0x-prefixed hex",
+            InputType::Ownership { recursive: false } => {
+                "Enter owner: user, user:group, user: or :group (names or IDs)"
+            }
+            InputType::Ownership { recursive: true } => {
+                "Enter owner for the whole tree: user, user:group, user: or :group (names or IDs)"
+            }
+            InputType::Symlink { relative: true } => {
+                "Enter link name or directory (relative link, Tab: make it absolute)"
+            }
+            InputType::Symlink { relative: false } => {
+                "Enter link name or directory (absolute link, Tab: make it relative)"
+            }
+            InputType::HardLink => "Enter hard link name or directory",
+            InputType::Filter {
+                kind: MatchKind::Substring,
+            } => "Filter by substring (Tab: glob, Esc: clear)",
+            InputType::Filter {
+                kind: MatchKind::Glob,
+            } => "Filter by glob (Tab: regex, Esc: clear)",
+            InputType::Filter {
+                kind: MatchKind::Regex,
+            } => "Filter by regex (Tab: substring, Esc: clear)",
+            InputType::Query => {
+                "Enter query, like size>100M and mtime<7d and perm:o+w and type:f and user:root"
+            }
+            InputType::Search(SearchOptions { regex: false, .. }) => {
+                "Search file contents for a fixed string (Tab: regex, Shift-Tab: case)"
+            }
+            InputType::Search(SearchOptions { regex: true, .. }) => {
+                "Search file contents for a regex (Tab: fixed string, Shift-Tab: case)"
+            }
+            InputType::NewFile => "Enter new file path, optionally followed by -m MODE",
+            InputType::NewDirectory => "Enter new directory path, optionally followed by -m MODE",
+            InputType::RecursivePermission => {
+                "Enter mode for the whole tree: file mode[:directory mode], e.g. 644:755 or u+rwX"
+            }
         }
     }
 }
 
-impl Default for AppMode {
-    fn default() -> Self {
-        AppMode::Nav
-    }
-}
-
 /// The current state of the app.
 /// The dir_list tracks information about which entry is selected, via ListState.
 /// The events are used to mutate the state.
 pub struct App {
     pub user_input: String,
     pub mode: AppMode,
+    pub copy_options: CopyOptions,
     pub current_directory: PathBuf,
+    /// Everything read for the listing, before dotfiles are hidden and the filter is applied
+    entries: Vec<(FileListing, usize)>,
+    /// What `entries` shows after hiding and filtering
     pub dir_list: StatefulList<(FileListing, usize)>,
+    /// Marked entries, by path, so they stay marked while hidden or filtered out of view
+    pub marked: BTreeSet<PathBuf>,
+    pub trash_list: StatefulList<TrashEntry>,
+    pub jobs: Jobs,
+    pub messages: MessageLog,
+    pub preview: Previewer,
+    pub permission_editor: PermissionEditor,
+    pub users: Users,
+    /// Sort orders chosen for directories, by canonical path.  Others use the default.
+    pub sort_orders: HashMap<PathBuf, SortOrder>,
+    /// Whether dotfiles are listed
+    pub show_hidden: bool,
+    /// Only entries matching this are listed
+    pub filter: Option<Filter>,
+    /// Where the cursor was before filtering, so clearing the filter can put it back
+    filter_cursor: Option<Option<usize>>,
+    pub finder: Finder,
+    pub search: Search,
+    /// Workers started later report back through this
+    events: Sender<Event<Key>>,
+    /// While set, the query's matches are listed instead of the current directory
+    pub query: Option<QueryResults>,
+    /// Counts queries, so matches from an abandoned one are recognized
+    query_runs: usize,
+    /// Directories to go back and forward to
+    pub history: History,
 }
 
 impl App {
-    pub fn new() -> Self {
+    /// Background jobs and the preview worker report back through `events`.
+    pub fn new(events: Sender<Event<Key>>) -> Self {
         let default_path = PathBuf::from_str(".").expect("Should read current directory");
-        let dir_list =
-            list_of_dir(&default_path).expect("Should enumerate current directory listing");
+        let entries = list_of_dir(&default_path, &SortOrder::default())
+            .expect("Should enumerate current directory listing");
+        let dir_list = StatefulList::with_items(
+            entries
+                .iter()
+                .filter(|(listing, idx)| *idx < 2 || !is_hidden(listing))
+                .cloned()
+                .collect(),
+        );
         Self {
             current_directory: default_path,
+            entries,
             dir_list,
+            marked: BTreeSet::new(),
+            trash_list: StatefulList::with_items(vec![]),
             mode: AppMode::default(),
+            copy_options: CopyOptions::default(),
             user_input: String::new(),
+            jobs: Jobs::new(events.clone()),
+            messages: MessageLog::default(),
+            preview: Previewer::new(events.clone()),
+            permission_editor: PermissionEditor::default(),
+            users: Users::load(),
+            sort_orders: HashMap::new(),
+            show_hidden: false,
+            filter: None,
+            filter_cursor: None,
+            finder: Finder::new(events.clone()),
+            search: Search::new(events.clone()),
+            query: None,
+            query_runs: 0,
+            history: History::default(),
+            events,
         }
     }
 
-    /// Change the active directory
+    /// Interpret user-supplied paths relative to the directory being viewed.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.current_directory.join(path)
+    }
+
+    /// Re-read the listing of the current directory, keeping the cursor on the same entry.  Marks on entries that
+    /// have gone are dropped.  Query results stay as they were found; `refresh_after` looks at them again.
+    pub fn refresh(&mut self) -> Result<()> {
+        if self.query.is_none() {
+            self.entries = list_of_dir(&self.current_directory, &self.sort_order())?;
+        }
+        self.marked
+            .retain(|path| fs::symlink_metadata(path).is_ok());
+        self.narrow();
+        Ok(())
+    }
+
+    /// Re-read the listing after a job has worked on `touched`.  Of the query results, only matches at or below those
+    /// paths are looked at again, and dropped if they are gone.
+    fn refresh_after(&mut self, touched: &[PathBuf]) -> Result<()> {
+        if self.query.is_some() {
+            self.entries = std::mem::take(&mut self.entries)
+                .into_iter()
+                .filter_map(|(listing, idx)| {
+                    if !touched.iter().any(|path| listing.path.starts_with(path)) {
+                        return Some((listing, idx));
+                    }
+                    fs::symlink_metadata(&listing.path).ok()?;
+                    Some((FileListing::read(listing.path), idx))
+                })
+                .collect();
+        }
+        self.refresh()
+    }
+
+    /// The entries that pass the hidden-file setting and the filter, with nothing selected
+    fn narrowed(&self) -> StatefulList<(FileListing, usize)> {
+        StatefulList::with_items(
+            self.entries
+                .iter()
+                .filter(|(listing, idx)| *idx < 2 || self.shows(listing))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Show the entries already read that pass the hidden-file setting and the filter, keeping the cursor on the same
+    /// entry.
+    fn narrow(&mut self) {
+        let selected = self
+            .dir_list
+            .grab_selected()
+            .map(|(listing, _)| listing.path.clone());
+        self.dir_list = self.narrowed();
+        if let Some(path) = selected {
+            self.select_path(&path);
+        }
+    }
+
+    /// Apply a report from a background job.  Once a job finishes, the listings it may have changed are re-read.
+    pub fn handle_job_event(&mut self, event: JobEvent) -> Result<()> {
+        let job = match self.jobs.update(event) {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        let select = match &job.state {
+            JobState::Done => job.select_on_success.clone(),
+            JobState::Failed { error, causes } => {
+                let text = format!("{} failed: {}", job.description, error);
+                self.messages.push(Level::Error, text, causes.clone());
+                None
+            }
+            JobState::Cancelled => {
+                let text = format!("{} was cancelled", job.description);
+                self.messages.warning(text);
+                None
+            }
+            JobState::Running => None,
+        };
+        let (description, summary) = (job.description.clone(), job.summary.clone());
+        let touched = job.sources.clone();
+        if let Some(summary) = summary {
+            self.report_changes(&description, summary);
+        }
+        self.refresh_after(&touched)?;
+        if let Some(path) = select {
+            self.select_path(&path);
+        }
+        if self.mode == AppMode::Trash {
+            self.open_trash()?;
+        }
+        Ok(())
+    }
+
+    /// Where the given path appears in the current listing, ignoring the synthetic "." and ".." entries.
+    fn position_of(&self, path: &Path) -> Option<usize> {
+        let wanted = normalize(path);
+        self.dir_list
+            .items
+            .iter()
+            .position(|(listing, idx)| *idx > 1 && normalize(&listing.path) == wanted)
+    }
+
+    /// Move the cursor to the given path, if it appears in the current listing.
+    pub fn select_path(&mut self, path: &Path) {
+        if let Some(idx) = self.position_of(path) {
+            self.dir_list.state.select(Some(idx));
+        }
+    }
+
+    /// The preview of the entry under the cursor, or of the search hit under it while browsing search results
+    pub fn preview_selected(&mut self) -> PreviewState<'_> {
+        let path = if self.mode == AppMode::Search {
+            self.search.hits.grab_selected().map(|hit| hit.path.clone())
+        } else {
+            self.dir_list
+                .grab_selected()
+                .map(|(listing, _)| listing.path.clone())
+        };
+        self.preview.show(path.as_deref())
+    }
+
+    /// Check what has been typed into the prompt so far, before leaving it.
+    pub fn validate_input(&self, input_type: InputType) -> Result<()> {
+        match input_type {
+            InputType::Permission | InputType::RecursivePermission => {
+                self.permission_preview().map(|_| ())
+            }
+            InputType::Ownership { .. } => self.ownership_preview().map(|_| ()),
+            InputType::NewFile | InputType::NewDirectory => {
+                split_mode(&self.user_input).map(|_| ())
+            }
+            InputType::Filter { kind } if !self.user_input.is_empty() => {
+                Filter::new(&self.user_input, kind).map(|_| ())
+            }
+            InputType::Search(options) => options.compile(&self.user_input).map(|_| ()),
+            InputType::Query => Query::parse(&self.user_input, &self.users).map(|_| ()),
+            InputType::GotoOffset if parse_offset(&self.user_input).is_none() => {
+                bail!("Invalid offset {:?}", self.user_input)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The mode the entry under the cursor would get from the mode typed so far
+    pub fn permission_preview(&self) -> Result<String> {
+        let change: ModeChange = self.user_input.parse()?;
+        let path = self
+            .targets()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Nothing selected"))?;
+        let m = fs::metadata(&path)?;
+        let old = m.permissions().mode();
+        let new = change.spec(m.is_dir()).apply(old, m.is_dir());
+        let mut preview = format!("{} -> {}", describe_mode(old), describe_mode(new));
+        if let Some(warning) = mode_warning(new, m.is_dir()) {
+            preview.push_str(&format!(" (warning: {})", warning));
+        }
+        Ok(preview)
+    }
+
+    /// Who would own the entry under the cursor after the ownership change typed so far
+    pub fn ownership_preview(&self) -> Result<String> {
+        let owner = self.users.parse_owner(&self.user_input)?;
+        let path = self
+            .targets()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Nothing selected"))?;
+        let m = fs::symlink_metadata(&path)?;
+        let describe = |uid, gid| {
+            format!(
+                "{}:{}",
+                self.users
+                    .user_name(uid)
+                    .map_or(uid.to_string(), str::to_string),
+                self.users
+                    .group_name(gid)
+                    .map_or(gid.to_string(), str::to_string)
+            )
+        };
+        Ok(format!(
+            "{} -> {}",
+            describe(m.uid(), m.gid()),
+            describe(owner.uid.unwrap_or(m.uid()), owner.gid.unwrap_or(m.gid()))
+        ))
+    }
+
+    /// Open the permission editor on the mode of the entry under the cursor.
+    pub fn open_permission_editor(&mut self) -> Result<()> {
+        let path = self
+            .targets()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Nothing selected"))?;
+        let m = fs::metadata(&path)?;
+        self.permission_editor = PermissionEditor::new(m.permissions().mode(), m.is_dir());
+        self.mode = AppMode::PermissionEditor;
+        Ok(())
+    }
+
+    /// Close the permission editor, giving every target the mode it shows.
+    pub fn apply_permission_editor(&mut self) -> Result<()> {
+        self.mode = AppMode::Nav;
+        let mode = format!("{:04o}", self.permission_editor.mode);
+        self.set_permissions(&mode, false)
+    }
+
+    /// Show the hex view of the selected file from the given offset.
+    pub fn goto_offset(&mut self, input: &str) -> Result<()> {
+        match parse_offset(input) {
+            Some(offset) => self.preview.jump_to(offset),
+            None => bail!("Invalid offset {:?}", input),
+        }
+        Ok(())
+    }
+
+    pub fn is_marked(&self, listing: &FileListing) -> bool {
+        self.marked.contains(&listing.path)
+    }
+
+    /// The real entries in view, leaving out the synthetic "." and ".."
+    fn listed_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.dir_list
+            .items
+            .iter()
+            .filter(|(_, idx)| *idx > 1)
+            .map(|(listing, _)| &listing.path)
+    }
+
+    /// Mark or unmark the entry under the cursor, then move down.
+    pub fn toggle_mark(&mut self) {
+        if let Some((listing, idx)) = self.dir_list.grab_selected() {
+            if *idx > 1 && !self.marked.remove(&listing.path) {
+                self.marked.insert(listing.path.clone());
+            }
+        }
+        self.dir_list.next();
+    }
+
+    /// Mark every real entry in view.
+    pub fn mark_all(&mut self) {
+        let listed: Vec<PathBuf> = self.listed_paths().cloned().collect();
+        self.marked.extend(listed);
+    }
+
+    /// Flip the marks on the real entries in view.  Marks on entries out of view stay as they are.
+    pub fn invert_marks(&mut self) {
+        let listed: Vec<PathBuf> = self.listed_paths().cloned().collect();
+        for path in listed {
+            if !self.marked.remove(&path) {
+                self.marked.insert(path);
+            }
+        }
+    }
+
+    /// How many marked entries are hidden or filtered out of view
+    pub fn marked_out_of_view(&self) -> usize {
+        let in_view = self
+            .listed_paths()
+            .filter(|path| self.marked.contains(*path))
+            .count();
+        self.marked.len() - in_view
+    }
+
+    /// The entries an operation applies to: every marked entry, in view or not, or the one under the cursor if
+    /// nothing is marked.  The synthetic "." and ".." entries are never included.
+    pub fn targets(&self) -> Vec<PathBuf> {
+        if !self.marked.is_empty() {
+            return self.marked.iter().cloned().collect();
+        }
+        self.dir_list
+            .grab_selected()
+            .filter(|(_, idx)| *idx > 1)
+            .map(|(listing, _)| listing.path.clone())
+            .into_iter()
+            .collect()
+    }
+
+    /// Pair each source with its destination.  A directory target receives the sources under their own names;
+    /// anything else is only allowed as the new name of a single source.
+    fn destinations(&self, sources: &[PathBuf], target: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let target = self.resolve(target);
+        if target.is_dir() {
+            Ok(sources
+                .iter()
+                .filter_map(|source| Some((source.clone(), target.join(source.file_name()?))))
+                .collect())
+        } else if sources.len() == 1 {
+            Ok(vec![(sources[0].clone(), target)])
+        } else {
+            bail!(
+                "{:?} must be an existing directory to receive {} entries",
+                target,
+                sources.len()
+            )
+        }
+    }
+
+    /// Describe a bulk operation for the jobs panel, e.g. `Copy "a" to "b"` or `Copy 3 entries to "dir"`.
+    fn describe(verb: &str, pairs: &[(PathBuf, PathBuf)]) -> String {
+        match pairs {
+            [(from, to)] => format!("{} {:?} to {:?}", verb, from, to),
+            _ => {
+                let dir = pairs
+                    .first()
+                    .and_then(|(_, to)| to.parent())
+                    .unwrap_or_else(|| Path::new(""));
+                format!("{} {} entries to {:?}", verb, pairs.len(), dir)
+            }
+        }
+    }
+
+    /// Find everything below the current directory that matches a query, and list it in place of the directory.
+    pub fn start_query(&mut self, text: &str) -> Result<()> {
+        let query = Query::parse(text, &self.users)?;
+        let root = canonicalize(&self.current_directory)
+            .unwrap_or_else(|_| self.current_directory.clone());
+        self.query_runs += 1;
+        self.query = Some(QueryResults::start(
+            &root,
+            text,
+            query,
+            self.query_runs,
+            self.events.clone(),
+        ));
+        self.filter = None;
+        self.filter_cursor = None;
+        self.entries.clear();
+        self.dir_list = StatefulList::with_items(vec![]);
+        self.marked.clear();
+        Ok(())
+    }
+
+    /// Add matches from the query's walker to the listing.
+    pub fn insert_query_matches(&mut self, event: QueryEvent) {
+        let query = match &mut self.query {
+            Some(query) => query,
+            None => return,
+        };
+        let found = query.insert(event);
+        let first = query.matches.len() - found.len();
+        for (idx, listing) in found.into_iter().enumerate() {
+            let entry = (listing, first + idx + 2);
+            if self.shows(&entry.0) {
+                self.dir_list.items.push(entry.clone());
+            }
+            self.entries.push(entry);
+        }
+        if self.dir_list.state.selected().is_none() && !self.dir_list.items.is_empty() {
+            self.dir_list.state.select(Some(0));
+        }
+    }
+
+    /// Stop showing query results and list the current directory again.
+    pub fn close_query(&mut self) -> Result<()> {
+        self.query = None;
+        self.marked.clear();
+        self.refresh()
+    }
+
+    /// Whether an entry passes the hidden-file setting and the filter
+    fn shows(&self, listing: &FileListing) -> bool {
+        (self.show_hidden || !is_hidden(listing))
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.find(&listing.to_string()).is_some())
+    }
+
+    /// Show or hide dotfiles.
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+        self.narrow();
+    }
+
+    /// Start typing a filter, or edit the one in use.
+    pub fn open_filter(&mut self) {
+        let kind = match &self.filter {
+            Some(filter) => {
+                self.user_input = filter.pattern.clone();
+                filter.kind
+            }
+            None => {
+                self.filter_cursor = Some(self.dir_list.state.selected());
+                MatchKind::default()
+            }
+        };
+        self.mode = AppMode::Input(InputType::Filter { kind });
+    }
+
+    /// Narrow the listing to what the input matches.  While the input doesn't parse, the last filter stays.
+    pub fn update_filter(&mut self, kind: MatchKind) {
+        if self.user_input.is_empty() {
+            self.filter = None;
+        } else {
+            match Filter::new(&self.user_input, kind) {
+                Ok(filter) => self.filter = Some(filter),
+                Err(_) => return,
+            }
+        }
+        self.narrow();
+        // Keep the cursor on a match, if there are any
+        let on_entry = matches!(self.dir_list.grab_selected(), Some((_, idx)) if *idx > 1);
+        if self.filter.is_some() && !on_entry && self.dir_list.items.len() > 2 {
+            self.dir_list.state.select(Some(2));
+        }
+    }
+
+    /// How many entries the filter being typed matches, or why it doesn't parse
+    pub fn filter_preview(&self, kind: MatchKind) -> Result<String> {
+        Filter::new(&self.user_input, kind)?;
+        let matches = self.dir_list.items.len().saturating_sub(2);
+        Ok(format!(
+            "{} matching entr{}",
+            matches,
+            if matches == 1 { "y" } else { "ies" }
+        ))
+    }
+
+    /// List everything again, with the cursor back where it was before filtering.
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+        self.narrow();
+        if let Some(selected) = self.filter_cursor.take() {
+            let last = self.dir_list.items.len().saturating_sub(1);
+            self.dir_list
+                .state
+                .select(selected.map(|idx| idx.min(last)));
+        }
+    }
+
+    fn sort_order_of(&self, dir: &Path) -> SortOrder {
+        canonicalize(dir)
+            .ok()
+            .and_then(|dir| self.sort_orders.get(&dir).copied())
+            .unwrap_or_default()
+    }
+
+    /// How the current directory is sorted
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order_of(&self.current_directory)
+    }
+
+    /// Change how the current directory is sorted and re-read it.  The choice sticks to this directory.
+    pub fn change_sort(&mut self, change: impl FnOnce(&mut SortOrder)) -> Result<()> {
+        if self.query.is_some() {
+            bail!("Query results are listed in the order they are found");
+        }
+        let mut order = self.sort_order();
+        change(&mut order);
+        self.sort_orders
+            .insert(canonicalize(&self.current_directory)?, order);
+        self.refresh()
+    }
+
+    /// Change the active directory, remembering the one left for going back.  Going up a level puts the cursor on
+    /// the directory just left.
     pub fn change_dir(&mut self, path: &Path) -> Result<()> {
+        let left = self.visit();
+        let (left_dir, dir) = (canonicalize(&left.dir).ok(), canonicalize(path).ok());
+        self.open_dir(path)?;
+        if let Some(left_dir) = &left_dir {
+            if left_dir.parent() == dir.as_deref() {
+                self.select_path(left_dir);
+            }
+        }
+        // Staying put, like revealing a file next to the cursor, isn't worth going back to
+        if left_dir != dir {
+            self.history.push(left);
+        }
+        Ok(())
+    }
+
+    /// Where the listing is now, to come back to later
+    fn visit(&self) -> Visit {
+        Visit {
+            dir: self.current_directory.clone(),
+            selected: self
+                .dir_list
+                .grab_selected()
+                .filter(|(_, idx)| *idx > 1)
+                .map(|(listing, _)| listing.path.clone()),
+            state: self.dir_list.state.clone(),
+        }
+    }
+
+    /// Return to a directory as it was left.
+    fn revisit(&mut self, visit: &Visit) -> Result<()> {
+        self.open_dir(&visit.dir)?;
+        self.dir_list.state = visit.state.clone();
+        if let Some(path) = &visit.selected {
+            self.select_path(path);
+        }
+        // Entries may have gone since
+        if let Some(idx) = self.dir_list.state.selected() {
+            if idx >= self.dir_list.items.len() {
+                self.dir_list.state.select(None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Go back to the directory before this one.  One that can't be opened any more is dropped from the history.
+    pub fn go_back(&mut self) -> Result<()> {
+        let visit = match self.history.back_to() {
+            Some(visit) => visit.clone(),
+            None => bail!("Nowhere to go back to"),
+        };
+        let current = self.visit();
+        if let Err(e) = self.revisit(&visit) {
+            self.history.forget_back();
+            return Err(e.context(format!("Cannot go back to {:?}", visit.dir)));
+        }
+        self.history.back(current);
+        Ok(())
+    }
+
+    /// Go forward again to a directory gone back from.  One that can't be opened any more is dropped from the
+    /// history.
+    pub fn go_forward(&mut self) -> Result<()> {
+        let visit = match self.history.forward_to() {
+            Some(visit) => visit.clone(),
+            None => bail!("Nowhere to go forward to"),
+        };
+        let current = self.visit();
+        if let Err(e) = self.revisit(&visit) {
+            self.history.forget_forward();
+            return Err(e.context(format!("Cannot go forward to {:?}", visit.dir)));
+        }
+        self.history.forward(current);
+        Ok(())
+    }
+
+    /// List a directory in place of the current one.
+    fn open_dir(&mut self, path: &Path) -> Result<()> {
+        if !path.is_dir() {
+            bail!("{:?} is not a directory", path);
+        }
+        self.entries = list_of_dir(path, &self.sort_order_of(path))?;
+        // Filters and marks apply to the directory they were made in
+        self.filter = None;
+        self.filter_cursor = None;
+        self.marked.clear();
+        self.query = None;
+        self.dir_list = self.narrowed();
         self.current_directory = path.to_path_buf();
-        self.dir_list = list_of_dir(&self.current_directory).unwrap();
         Ok(())
     }
 
-    /// Copy the selected file to the target location
+    /// Change to a directory typed in by the user, relative to the current one.
+    pub fn jump_to(&mut self, path: &Path) -> Result<()> {
+        self.change_dir(&self.resolve(path))
+    }
+
+    /// Copy the marked or selected entries, recursively for directories.  If the target is a directory, the copies
+    /// go inside it.
     pub fn copy_selected(&mut self, target: &Path) -> Result<()> {
-        if target.is_dir() {
-            // FIXME - this should really grab the filename and build a new path
+        let sources = self.targets();
+        if sources.is_empty() {
             return Ok(());
         }
-        // First, create the destination.
-        let mut target = File::create(target)?;
+        let pairs = self.destinations(&sources, target)?;
+        let options = self.copy_options;
+        let select = pairs.first().map(|(_, to)| to.clone());
+        self.jobs.spawn(
+            Self::describe("Copy", &pairs),
+            sources,
+            Sizing::Measure,
+            select,
+            move |progress| {
+                for (from, to) in &pairs {
+                    copy_path(from, to, &options, progress)?;
+                }
+                Ok(())
+            },
+        );
+        self.marked.clear();
+        Ok(())
+    }
+
+    /// The entry under the cursor, and where a link to it typed in as `at` would go
+    fn link_paths(&self, at: &Path) -> Result<(PathBuf, PathBuf)> {
+        let source = match self.dir_list.grab_selected() {
+            Some((listing, idx)) if *idx > 1 => listing.path.clone(),
+            _ => bail!("Select an entry to link to"),
+        };
+        let mut pairs = self.destinations(&[source], at)?;
+        Ok(pairs.remove(0))
+    }
+
+    /// Select a newly made link if it's in view, and say what was done.
+    fn link_created(&mut self, link: &Path, text: String) -> Result<()> {
+        self.refresh()?;
+        self.select_path(link);
+        self.messages.info(text);
+        Ok(())
+    }
+
+    /// Create an empty file, or update an existing file's times, and put the cursor on it.
+    pub fn new_file(&mut self, input: &str) -> Result<()> {
+        let (path, mode) = split_mode(input)?;
+        let path = self.resolve(Path::new(path));
+        touch(&path, mode.as_ref())?;
+        self.reveal(&normalize(&path))
+    }
+
+    /// Create a directory and any missing parents, and put the cursor on it.
+    pub fn new_directory(&mut self, input: &str) -> Result<()> {
+        let (path, mode) = split_mode(input)?;
+        let path = self.resolve(Path::new(path));
+        make_dir(&path, mode.as_ref())?;
+        self.reveal(&normalize(&path))
+    }
+
+    /// Make a symbolic link to the entry under the cursor.  A relative link is worked out from the directory
+    /// the link ends up in, so it keeps working if both move together.
+    pub fn symlink_selected(&mut self, at: &Path, relative: bool) -> Result<()> {
+        let (source, link) = self.link_paths(at)?;
+        let (source, link) = (normalize(&source), normalize(&link));
+        let target = match link.parent() {
+            Some(dir) if relative => relative_path(dir, &source),
+            _ => source,
+        };
+        std::os::unix::fs::symlink(&target, &link)
+            .with_context(|| format!("creating link {:?}", link))?;
+        self.link_created(&link, format!("Linked {:?} -> {:?}", link, target))
+    }
+
+    /// Make a hard link to the entry under the cursor.
+    pub fn hard_link_selected(&mut self, at: &Path) -> Result<()> {
+        let (source, link) = self.link_paths(at)?;
+        match fs::hard_link(&source, &link) {
+            Ok(()) => {}
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => bail!(
+                "Cannot hard link {:?} from {:?}: hard links can't cross filesystems",
+                source,
+                link
+            ),
+            Err(e) => {
+                return Err(e).with_context(|| format!("hard linking {:?} to {:?}", link, source))
+            }
+        }
+        self.link_created(&link, format!("Hard linked {:?} to {:?}", link, source))
+    }
+
+    /// Switch copies between plain mode and archive mode, which preserves every attribute like `cp -a`.
+    pub fn toggle_archive(&mut self) {
+        let dereference = self.copy_options.dereference;
+        self.copy_options = if self.copy_options.is_archive() {
+            CopyOptions::default()
+        } else {
+            CopyOptions::archive()
+        };
+        self.copy_options.dereference = dereference;
+    }
+
+    /// Switch copies between recreating symbolic links and copying what they point to.
+    pub fn toggle_dereference(&mut self) {
+        self.copy_options.dereference = !self.copy_options.dereference;
+    }
+
+    /// Where the link under the cursor points, relative to the link's directory if the target is relative
+    fn selected_link_target(&self) -> Result<(PathBuf, LinkState)> {
+        let (listing, _) = self
+            .dir_list
+            .grab_selected()
+            .ok_or_else(|| anyhow!("Nothing selected"))?;
+        let link = listing
+            .link
+            .as_ref()
+            .ok_or_else(|| anyhow!("{:?} is not a symbolic link", listing.path))?;
+        let dir = listing.path.parent().unwrap_or_else(|| Path::new("/"));
+        Ok((dir.join(&link.target), link.state))
+    }
+
+    /// Open the fuzzy finder over everything below the current directory.
+    pub fn open_finder(&mut self) {
+        let root = canonicalize(&self.current_directory)
+            .unwrap_or_else(|_| self.current_directory.clone());
+        self.finder.open(&root);
+        self.mode = AppMode::Finder;
+    }
+
+    /// Close the finder and go to the chosen path.
+    pub fn goto_found(&mut self) -> Result<()> {
+        let path = match self.finder.selected() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.finder.close();
+        self.mode = AppMode::Nav;
+        self.reveal(&path)
+    }
+
+    /// Ask what to search for, with the options of the last search.
+    pub fn open_search_prompt(&mut self) {
+        self.mode = AppMode::Input(InputType::Search(self.search.options));
+    }
+
+    /// Go back to the results of the last search.
+    pub fn open_search_results(&mut self) {
+        self.mode = AppMode::Search;
+        self.preview_search_hit();
+    }
+
+    /// Search the contents of everything below the current directory.
+    pub fn start_search(&mut self, pattern: &str, options: SearchOptions) -> Result<()> {
+        let root = canonicalize(&self.current_directory)
+            .unwrap_or_else(|_| self.current_directory.clone());
+        self.search.start(&root, pattern, options)?;
+        self.mode = AppMode::Search;
+        Ok(())
+    }
+
+    /// Take hits from the search worker, previewing the first one to come in.
+    pub fn insert_search_hits(&mut self, event: SearchEvent) {
+        if self.search.insert(event) && self.mode == AppMode::Search {
+            self.preview_search_hit();
+        }
+    }
+
+    /// Scroll the preview to the selected hit's line.
+    fn preview_search_hit(&mut self) {
+        if let Some(hit) = self.search.hits.grab_selected() {
+            self.preview.jump_to_line(&hit.path, hit.offset);
+        }
+    }
+
+    pub fn next_search_hit(&mut self) {
+        self.search.hits.next();
+        self.preview_search_hit();
+    }
+
+    pub fn previous_search_hit(&mut self) {
+        self.search.hits.previous();
+        self.preview_search_hit();
+    }
+
+    /// Leave the results for the selected hit's directory, with its file selected and previewed at the line.
+    pub fn goto_search_hit(&mut self) -> Result<()> {
+        let path = match self.search.hits.grab_selected() {
+            Some(hit) => hit.path.clone(),
+            None => return Ok(()),
+        };
+        self.mode = AppMode::Nav;
+        self.reveal(&path)
+    }
 
-        // Grab the input contents
-        let contents = if let Some((selected, _)) = self.dir_list.grab_selected() {
-            selected
-                .contents()
-                .unwrap_or_default()
-                .unwrap_or_else(String::new)
+    /// Show the directory holding `path`, with `path` selected.
+    fn reveal(&mut self, path: &Path) -> Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow!("{:?} has no parent directory", path))?;
+        self.change_dir(dir)?;
+        self.select_path(path);
+        Ok(())
+    }
+
+    /// Follow the link under the cursor all the way: into the directory it leads to, or to the file.
+    pub fn follow_link(&mut self) -> Result<()> {
+        let (target, state) = self.selected_link_target()?;
+        if state != LinkState::Resolves {
+            bail!("{:?} cannot be followed", target);
+        }
+        let target = canonicalize(&target)?;
+        if target.is_dir() {
+            self.change_dir(&target)
         } else {
-            String::new()
+            self.reveal(&target)
+        }
+    }
+
+    /// Go to the directory the link under the cursor points into, with its target selected.  This works for
+    /// broken links too, as long as that directory exists.
+    pub fn goto_link_dir(&mut self) -> Result<()> {
+        let (target, _) = self.selected_link_target()?;
+        self.reveal(&normalize(&target))
+    }
+
+    /// Move or rename the marked or selected entries.  If the target is a directory, the entries keep their names.
+    pub fn move_selected(&mut self, target: &Path) -> Result<()> {
+        let sources = self.targets();
+        if sources.is_empty() {
+            return Ok(());
+        }
+        let pairs = self.destinations(&sources, target)?;
+        let select = pairs.first().map(|(_, to)| to.clone());
+        self.jobs.spawn(
+            Self::describe("Move", &pairs),
+            sources,
+            // Moves within a filesystem are a rename; only copying across needs the totals
+            Sizing::Indeterminate,
+            select,
+            move |progress| {
+                for (from, to) in &pairs {
+                    move_path(from, to, progress)?;
+                }
+                Ok(())
+            },
+        );
+        self.marked.clear();
+        Ok(())
+    }
+
+    /// Move the marked or selected entries into the trash.
+    pub fn trash_selected(&mut self) -> Result<()> {
+        let sources: Vec<PathBuf> = self.targets().iter().map(|p| normalize(p)).collect();
+        let description = match sources.as_slice() {
+            [] => return Ok(()),
+            [source] => format!("Trash {:?}", source),
+            _ => format!("Trash {} entries", sources.len()),
         };
+        let trash = Trash::home()?;
+        self.jobs.spawn(
+            description,
+            sources.clone(),
+            Sizing::Indeterminate,
+            None,
+            move |progress| {
+                for source in &sources {
+                    trash.put(source, progress)?;
+                }
+                Ok(())
+            },
+        );
+        self.marked.clear();
+        Ok(())
+    }
+
+    /// Switch to the trash view, re-reading its contents.
+    pub fn open_trash(&mut self) -> Result<()> {
+        self.trash_list = StatefulList::with_items(Trash::home()?.list()?);
+        self.mode = AppMode::Trash;
+        Ok(())
+    }
 
-        // Write contents to file
-        write!(target, "{}", contents)?;
-        // Re-read directory list
-        self.dir_list = list_of_dir(&self.current_directory).unwrap();
+    /// Put the selected trash entry back where it came from.
+    pub fn restore_selected_trash(&mut self) -> Result<()> {
+        if let Some(entry) = self.trash_list.grab_selected() {
+            let (trash, entry) = (Trash::home()?, entry.clone());
+            let description = format!("Restore {:?}", entry.original_path);
+            self.jobs.spawn(
+                description,
+                vec![trash.file_path(&entry)],
+                Sizing::Indeterminate,
+                Some(entry.original_path.clone()),
+                move |progress| trash.restore(&entry, progress),
+            );
+        }
+        Ok(())
+    }
+
+    /// Permanently delete the selected trash entry.
+    pub fn purge_selected_trash(&mut self) -> Result<()> {
+        if let Some(entry) = self.trash_list.grab_selected() {
+            let (trash, entry) = (Trash::home()?, entry.clone());
+            let description = format!("Delete {:?} permanently", entry.original_path);
+            self.jobs.spawn(
+                description,
+                vec![],
+                Sizing::Indeterminate,
+                None,
+                move |_| trash.purge(&entry),
+            );
+        }
         Ok(())
     }
 
@@ -306,21 +1349,81 @@ impl App {
         Ok(())
     }
 
-    /// Attempt to change the currently selected file's permission string
-    pub fn set_permissions(&mut self, new_perms: &str) -> Result<()> {
-        if let Some((listing, _)) = self.dir_list.grab_selected() {
-            if !listing.is_directory {
-                let f = File::open(&listing.path)?;
-                let m = f.metadata()?;
-                let mut permissions = m.permissions();
-                if let Some(new_mode) = string_to_permissions(new_perms) {
-                    permissions.set_mode(new_mode as u32);
-                }
-                f.set_permissions(permissions)?;
+    /// Change the permissions of the marked or selected entries, and everything below them if `recursive`
+    pub fn set_permissions(&mut self, new_perms: &str, recursive: bool) -> Result<()> {
+        let change: ModeChange = new_perms.parse()?;
+        self.change_targets("permissions", recursive, move |path, summary, progress| {
+            change.apply(path, recursive, summary, progress)
+        })
+    }
+
+    /// Give the marked or selected entries a new owner or group, and everything below them if `recursive`
+    pub fn set_ownership(&mut self, owner: &str, recursive: bool) -> Result<()> {
+        let owner = self.users.parse_owner(owner)?;
+        self.change_targets("ownership", recursive, move |path, summary, progress| {
+            owner.apply(path, recursive, summary, progress)
+        })
+    }
+
+    /// Run a permission or ownership change over the marked or selected entries.  Changing just the entries
+    /// themselves is done on the spot; going through everything below them runs as a job.
+    fn change_targets<F>(&mut self, what: &str, recursive: bool, apply: F) -> Result<()>
+    where
+        F: Fn(&Path, &mut ChangeSummary, &mut dyn Progress) -> Result<()> + Send + 'static,
+    {
+        let targets = self.targets();
+        let description = match targets.as_slice() {
+            [] => return Ok(()),
+            [target] => format!("Change {} of {:?}", what, target),
+            _ => format!("Change {} of {} entries", what, targets.len()),
+        };
+        self.marked.clear();
+        if !recursive {
+            let mut summary = ChangeSummary::default();
+            for path in &targets {
+                apply(path, &mut summary, &mut NoProgress)?;
             }
+            self.report_changes(&description, summary);
+            return Ok(());
         }
+        let description = format!("{} recursively", description);
+        self.jobs.spawn(
+            description,
+            targets.clone(),
+            Sizing::Measure,
+            None,
+            move |progress| {
+                let mut summary = ChangeSummary::default();
+                let result = targets
+                    .iter()
+                    .try_for_each(|path| apply(path, &mut summary, progress));
+                // What was changed before a cancellation is still worth knowing
+                progress.summarize(summary);
+                result
+            },
+        );
         Ok(())
     }
+
+    /// Sum up a permission or ownership change in the notification area.
+    fn report_changes(&mut self, description: &str, summary: ChangeSummary) {
+        let text = format!(
+            "{}: changed {} entries ({} already matched, {} failed)",
+            description,
+            summary.changed,
+            summary.unchanged,
+            summary.failed.len()
+        );
+        if summary.failed.is_empty() {
+            self.messages.info(text);
+        } else {
+            self.messages.push(Level::Warning, text, summary.failed);
+        }
+        // After the summary, so the notification area shows the warning
+        for warning in summary.warnings {
+            self.messages.warning(warning);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -329,11 +1432,67 @@ mod test {
     use pretty_assertions::assert_eq;
 
     #[test]
-    fn test_roundtrip_permissions() {
-        let str = "---rwxr-x";
-        assert_eq!(
-            str,
-            permissions_to_string(string_to_permissions(str).unwrap())
-        );
+    fn test_device_numbers() {
+        // /dev/sda3 and a device with a minor number above 255
+        assert_eq!(device_numbers(libc::makedev(8, 3)), (8, 3));
+        assert_eq!(device_numbers(libc::makedev(259, 0x1005)), (259, 0x1005));
+        assert_eq!(device_numbers(0x803), (8, 3));
+    }
+
+    #[test]
+    fn test_split_mode() {
+        let (path, mode) = split_mode("new dir -m 0700").unwrap();
+        assert_eq!(path, "new dir");
+        assert_eq!(mode, Some(ModeSpec::Absolute(0o700)));
+        assert_eq!(split_mode("plain").unwrap(), ("plain", None));
+        assert!(split_mode("bad -m 99").is_err());
+        assert!(split_mode("").is_err());
+    }
+
+    #[test]
+    fn test_marks_survive_filtering() {
+        let dir = std::env::temp_dir().join(format!("file_utility_marks_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["apple", "apricot", "banana"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let (events, _rx) = std::sync::mpsc::channel();
+        let mut app = App::new(events);
+        app.change_dir(&dir).unwrap();
+        app.mark_all();
+
+        // Narrowing and widening again doesn't lose the mark on the entry filtered out
+        app.user_input = "ap".to_string();
+        app.update_filter(MatchKind::Substring);
+        assert_eq!(app.dir_list.items.len(), 4);
+        assert_eq!(app.marked_out_of_view(), 1);
+        app.user_input.clear();
+        app.update_filter(MatchKind::Substring);
+        assert_eq!(app.targets().len(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_go_back_to_removed_dir() {
+        let dir = std::env::temp_dir().join(format!("file_utility_history_{}", std::process::id()));
+        fs::create_dir_all(dir.join("gone")).unwrap();
+        fs::create_dir_all(dir.join("here")).unwrap();
+        let (events, _rx) = std::sync::mpsc::channel();
+        let mut app = App::new(events);
+        app.change_dir(&dir).unwrap();
+        app.change_dir(&dir.join("gone")).unwrap();
+        app.change_dir(&dir.join("here")).unwrap();
+        fs::remove_dir(dir.join("gone")).unwrap();
+
+        // The removed directory is dropped and nothing moves; the next step back goes past it
+        assert!(app.go_back().is_err());
+        assert_eq!(app.current_directory, dir.join("here"));
+        app.go_back().unwrap();
+        assert_eq!(app.current_directory, dir);
+        app.go_forward().unwrap();
+        assert_eq!(app.current_directory, dir.join("here"));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -2,7 +2,7 @@
 //!
 //! Taken from <https://github.com/fdehau/tui-rs/blob/master/examples/util/event.rs>.
 //!
-//! I made no modification.
+//! I added the `Job`, `Preview`, `Finder`, `Search` and `Query` events, which worker threads send through `Events::sender()`.
 
 use std::io;
 use std::sync::mpsc;
@@ -12,15 +12,26 @@ use std::time::Duration;
 use termion::event::Key;
 use termion::input::TermRead;
 
+use crate::{
+    finder::FinderEvent, jobs::JobEvent, preview::PreviewEvent, query::QueryEvent,
+    search::SearchEvent,
+};
+
 pub enum Event<I> {
     Input(I),
     Tick,
+    Job(JobEvent),
+    Preview(PreviewEvent),
+    Finder(FinderEvent),
+    Search(SearchEvent),
+    Query(QueryEvent),
 }
 
 /// A small event handler that wrap termion input and tick events. Each event
 /// type is handled in its own thread and returned to a common `Receiver`
 pub struct Events {
     rx: mpsc::Receiver<Event<Key>>,
+    tx: mpsc::Sender<Event<Key>>,
     _input_handle: thread::JoinHandle<()>,
     _tick_handle: thread::JoinHandle<()>,
 }
@@ -58,6 +69,7 @@ impl Events {
             })
         };
         let _tick_handle = {
+            let tx = tx.clone();
             thread::spawn(move || loop {
                 if let Err(err) = tx.send(Event::Tick) {
                     eprintln!("{}", err);
@@ -68,11 +80,17 @@ impl Events {
         };
         Events {
             rx,
+            tx,
             _input_handle,
             _tick_handle,
         }
     }
 
+    /// A handle other threads can use to feed events into the loop.
+    pub fn sender(&self) -> mpsc::Sender<Event<Key>> {
+        self.tx.clone()
+    }
+
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
         self.rx.recv()
     }
@@ -0,0 +1,144 @@
+//! `filter` narrows the directory listing to names matching a pattern.
+//!
+//! Patterns are substrings, shell globs or regular expressions.  Matching ignores case unless the pattern has an
+//! uppercase letter in it, like `less` and `ripgrep` do with smart case.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::Result;
+
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+use std::ops::Range;
+
+/// How a filter pattern is read
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MatchKind {
+    #[default]
+    Substring,
+    /// Matched against the whole name, so `*.rs` but not `rs`
+    Glob,
+    Regex,
+}
+
+impl MatchKind {
+    /// The next kind, for cycling through them with Tab
+    pub fn next(self) -> Self {
+        match self {
+            MatchKind::Substring => MatchKind::Glob,
+            MatchKind::Glob => MatchKind::Regex,
+            MatchKind::Regex => MatchKind::Substring,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MatchKind::Substring => "substring",
+            MatchKind::Glob => "glob",
+            MatchKind::Regex => "regex",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Matcher {
+    /// The pattern, lowercased unless case matters
+    Substring {
+        pattern: String,
+        ignore_case: bool,
+    },
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+/// A compiled filter pattern
+#[derive(Debug)]
+pub struct Filter {
+    pub pattern: String,
+    pub kind: MatchKind,
+    matcher: Matcher,
+}
+
+impl Filter {
+    pub fn new(pattern: &str, kind: MatchKind) -> Result<Self> {
+        let ignore_case = !pattern.chars().any(char::is_uppercase);
+        let matcher = match kind {
+            MatchKind::Substring => Matcher::Substring {
+                pattern: if ignore_case {
+                    pattern.to_lowercase()
+                } else {
+                    pattern.to_string()
+                },
+                ignore_case,
+            },
+            MatchKind::Glob => Matcher::Glob(
+                GlobBuilder::new(pattern)
+                    .case_insensitive(ignore_case)
+                    .literal_separator(true)
+                    .build()?
+                    .compile_matcher(),
+            ),
+            MatchKind::Regex => Matcher::Regex(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(ignore_case)
+                    .build()?,
+            ),
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            kind,
+            matcher,
+        })
+    }
+
+    /// Where `name` matches, as a byte range for highlighting.  Globs match the whole name.
+    pub fn find(&self, name: &str) -> Option<Range<usize>> {
+        match &self.matcher {
+            Matcher::Substring {
+                pattern,
+                ignore_case,
+            } => {
+                if *ignore_case {
+                    // Only lowercase ASCII, so byte offsets still line up with the original name
+                    let start = name.to_ascii_lowercase().find(pattern.as_str());
+                    // Non-ASCII letters need full case folding, which can change lengths; match without a range
+                    match start {
+                        Some(start) => Some(start..start + pattern.len()),
+                        None if name.to_lowercase().contains(pattern.as_str()) => Some(0..0),
+                        None => None,
+                    }
+                } else {
+                    let start = name.find(pattern.as_str())?;
+                    Some(start..start + pattern.len())
+                }
+            }
+            Matcher::Glob(glob) => glob.is_match(name).then_some(0..name.len()),
+            Matcher::Regex(regex) => regex.find(name).map(|m| m.range()),
+        }
+    }
+
+    /// Describe the filter for the listing's title, e.g. `glob "*.rs"`
+    pub fn label(&self) -> String {
+        format!("{} {:?}", self.kind.label(), self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_find() {
+        let find = |pattern, kind, name| Filter::new(pattern, kind).unwrap().find(name);
+        assert_eq!(find("read", MatchKind::Substring, "README.md"), Some(0..4));
+        // An uppercase letter makes the match case-sensitive
+        assert_eq!(find("Read", MatchKind::Substring, "README.md"), None);
+        assert_eq!(find("*.rs", MatchKind::Glob, "main.RS"), Some(0..7));
+        assert_eq!(find("*.rs", MatchKind::Glob, "main.rs.bak"), None);
+        assert_eq!(find("[0-9]+", MatchKind::Regex, "file10.txt"), Some(4..6));
+        assert_eq!(find("^x", MatchKind::Regex, "file10.txt"), None);
+        assert!(Filter::new("(", MatchKind::Regex).is_err());
+        assert!(Filter::new("[", MatchKind::Glob).is_err());
+    }
+}
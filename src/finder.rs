@@ -0,0 +1,681 @@
+//! `finder` is a fuzzy finder over everything below the current directory.
+//!
+//! A worker thread walks the tree with the [ignore](https://github.com/BurntSushi/ripgrep/tree/master/crates/ignore)
+//! crate, so `.gitignore` files are respected and hidden entries skipped.  The same thread ranks the paths against
+//! the query as they arrive and as the query changes, and sends the best back through the `Events` channel, so a
+//! large tree never holds up typing.  Paths are scored the way [fzf](https://github.com/junegunn/fzf) does it:
+//! matches at the start of words and runs of consecutive characters count for more, gaps between matched characters
+//! count against.
+
+// Events are keyed on termion keys
+use termion::event::Key;
+// Selection state for the results list
+use tui::widgets::ListState;
+
+use ignore::WalkBuilder;
+
+use crate::events::Event;
+
+// Threads and the state they share with the interface
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two batches from the walker
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most results kept and shown
+const MAX_RESULTS: usize = 200;
+
+// Scores, as in fzf's algorithm
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = -3;
+const SCORE_GAP_EXTENSION: i64 = -1;
+/// A match right after a path separator, where file and directory names start
+const BONUS_SEPARATOR: i64 = 9;
+/// A match at the start of a word
+const BONUS_BOUNDARY: i64 = 8;
+/// A match at a lowercase-to-uppercase or letter-to-digit change
+const BONUS_CAMEL: i64 = 7;
+/// Each character in an unbroken run, at least
+const BONUS_CONSECUTIVE: i64 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+/// The first query character's bonus counts this many times
+const FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+/// Compare two characters, ignoring case if asked to
+fn chars_eq(a: char, b: char, ignore_case: bool) -> bool {
+    if ignore_case {
+        a.to_lowercase().eq(b.to_lowercase())
+    } else {
+        a == b
+    }
+}
+
+/// Whether `query` appears in `text` in order, without scoring how well
+fn is_match(query: &[char], text: &[char], ignore_case: bool) -> bool {
+    let mut rest = text.iter();
+    query
+        .iter()
+        .all(|&q| rest.any(|&t| chars_eq(q, t, ignore_case)))
+}
+
+/// Smart case, as in listing filters: only a query with capitals in it is case-sensitive
+fn ignores_case(query: &[char]) -> bool {
+    !query.iter().any(|c| c.is_uppercase())
+}
+
+/// How much matching at `text[idx]` is worth, from the character before it
+fn position_bonus(text: &[char], idx: usize) -> i64 {
+    let current = text[idx];
+    let previous = match idx.checked_sub(1) {
+        Some(previous) => text[previous],
+        None => return BONUS_SEPARATOR,
+    };
+    if previous == '/' {
+        BONUS_SEPARATOR
+    } else if !previous.is_alphanumeric() && current.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if (previous.is_lowercase() && current.is_uppercase())
+        || (!previous.is_numeric() && current.is_numeric())
+    {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// Scratch space for `fuzzy_match`, reused from one path to the next so ranking a large tree doesn't allocate for
+/// every candidate
+#[derive(Debug, Default)]
+pub struct FuzzyMatcher {
+    bonuses: Vec<i64>,
+    /// `score[i * n + j]`: best score with `query[..=i]` matched and `query[i]` at `text[j]`
+    score: Vec<Option<i64>>,
+    /// `from[i * n + j]`: where `query[i - 1]` was for that score
+    from: Vec<usize>,
+    /// Bonus carried along a run of consecutive matches, as fzf gives every character the bonus of the run's start
+    run_bonus: Vec<i64>,
+}
+
+impl FuzzyMatcher {
+    /// Score `text` against `query`, which must appear in it in order but not necessarily together.  Returns the
+    /// score and the indices of the matched characters, or `None` if it doesn't match.
+    pub fn fuzzy_match(
+        &mut self,
+        query: &[char],
+        text: &[char],
+        ignore_case: bool,
+    ) -> Option<(i64, Vec<usize>)> {
+        let eq = |a: char, b: char| chars_eq(a, b, ignore_case);
+        // Cheap check first: most candidates don't match at all
+        if !is_match(query, text, ignore_case) {
+            return None;
+        }
+        if query.is_empty() {
+            return Some((0, Vec::new()));
+        }
+
+        let (m, n) = (query.len(), text.len());
+        let at = |i: usize, j: usize| i * n + j;
+        self.bonuses.clear();
+        self.bonuses.extend((0..n).map(|j| position_bonus(text, j)));
+        self.from.clear();
+        self.from.resize(m * n, 0);
+        self.run_bonus.clear();
+        self.run_bonus.resize(m * n, 0);
+        self.score.clear();
+        self.score.resize(m * n, None);
+        let (bonuses, score, from, run_bonus) = (
+            &self.bonuses,
+            &mut self.score,
+            &mut self.from,
+            &mut self.run_bonus,
+        );
+        for i in 0..m {
+            // The best earlier position for query[i - 1] with a gap before j, and where it was
+            let mut gapped: Option<(i64, usize)> = None;
+            for j in i..n {
+                if i > 0 && j >= 2 {
+                    let extended = gapped.map(|(s, k)| (s + SCORE_GAP_EXTENSION, k));
+                    let started = score[at(i - 1, j - 2)].map(|s| (s + SCORE_GAP_START, j - 2));
+                    gapped = match (extended, started) {
+                        (Some(a), Some(b)) => Some(if b.0 >= a.0 { b } else { a }),
+                        (a, b) => a.or(b),
+                    };
+                }
+                if !eq(query[i], text[j]) {
+                    continue;
+                }
+                if i == 0 {
+                    score[at(i, j)] = Some(SCORE_MATCH + bonuses[j] * FIRST_CHAR_MULTIPLIER);
+                    run_bonus[at(i, j)] = bonuses[j];
+                    continue;
+                }
+                let consecutive = (j >= 1)
+                    .then(|| score[at(i - 1, j - 1)])
+                    .flatten()
+                    .map(|s| {
+                        let bonus = run_bonus[at(i - 1, j - 1)]
+                            .max(bonuses[j])
+                            .max(BONUS_CONSECUTIVE);
+                        (s + SCORE_MATCH + bonus, bonus)
+                    });
+                let gap = gapped.map(|(s, k)| (s + SCORE_MATCH + bonuses[j], k));
+                match (consecutive, gap) {
+                    (Some((c, bonus)), g) if g.is_none_or(|(g, _)| c >= g) => {
+                        score[at(i, j)] = Some(c);
+                        from[at(i, j)] = j - 1;
+                        run_bonus[at(i, j)] = bonus;
+                    }
+                    (_, Some((g, k))) => {
+                        score[at(i, j)] = Some(g);
+                        from[at(i, j)] = k;
+                        run_bonus[at(i, j)] = bonuses[j];
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Take the best place for the last query character and trace the match back from it
+        let (best, mut j) = (0..n)
+            .filter_map(|j| score[at(m - 1, j)].map(|s| (s, j)))
+            .max_by_key(|&(s, j)| (s, std::cmp::Reverse(j)))?;
+        let mut positions = vec![0; m];
+        for i in (0..m).rev() {
+            positions[i] = j;
+            j = from[at(i, j)];
+        }
+        Some((best, positions))
+    }
+}
+
+/// Which parts of the tree are searched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FinderOptions {
+    /// Skip what `.gitignore` and `.ignore` files exclude
+    pub gitignore: bool,
+    /// Don't descend into other mounted filesystems
+    pub one_file_system: bool,
+}
+
+impl Default for FinderOptions {
+    fn default() -> Self {
+        Self {
+            gitignore: true,
+            one_file_system: true,
+        }
+    }
+}
+
+/// Sent from the worker with the best matches so far
+#[derive(Debug)]
+pub struct FinderEvent {
+    walk: usize,
+    /// The query the results were ranked for
+    query: String,
+    results: Vec<FinderMatch>,
+    /// How many paths have been found so far
+    found: usize,
+    done: bool,
+}
+
+/// One ranked path
+#[derive(Debug, Clone)]
+pub struct FinderMatch {
+    /// Index into the found paths
+    entry: usize,
+    /// Relative to the root
+    path: PathBuf,
+    score: i64,
+    /// Which characters of the path matched the query
+    pub positions: Vec<usize>,
+}
+
+/// A found path, relative to the root, as characters for matching
+struct Candidate {
+    path: PathBuf,
+    chars: Vec<char>,
+}
+
+/// What matches the query up to one of its characters
+struct Level {
+    matching: Vec<usize>,
+    /// The best of `matching`, best first, unless paths have been found since they were ranked
+    results: Option<Vec<FinderMatch>>,
+}
+
+/// The worker's side of the finder: every path found so far, and what matches each prefix of the query.
+///
+/// Whatever matches a longer query matched the shorter one, so typing a character only looks at the top level's
+/// matches again, and deleting one goes back to the level below.
+struct Ranker {
+    candidates: Vec<Candidate>,
+    query: Vec<char>,
+    /// `levels[i]` is for `query[..i]`, so there is always one for the empty query
+    levels: Vec<Level>,
+    matcher: FuzzyMatcher,
+}
+
+impl Default for Ranker {
+    fn default() -> Self {
+        Self {
+            candidates: Vec::new(),
+            query: Vec::new(),
+            levels: vec![Level {
+                matching: Vec::new(),
+                results: None,
+            }],
+            matcher: FuzzyMatcher::default(),
+        }
+    }
+}
+
+impl Ranker {
+    /// Switch to a new query, keeping the levels of the part it shares with the old one.
+    fn set_query(&mut self, query: &str) {
+        let query: Vec<char> = query.chars().collect();
+        let common = self
+            .query
+            .iter()
+            .zip(&query)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.levels.truncate(common + 1);
+        for len in common + 1..=query.len() {
+            let prefix = &query[..len];
+            let ignore_case = ignores_case(prefix);
+            let candidates = &self.candidates;
+            let matching = self.levels[len - 1]
+                .matching
+                .iter()
+                .copied()
+                .filter(|&entry| is_match(prefix, &candidates[entry].chars, ignore_case))
+                .collect();
+            self.levels.push(Level {
+                matching,
+                results: None,
+            });
+        }
+        self.query = query;
+    }
+
+    /// Take newly found paths, as they are below `root`.
+    fn add(&mut self, root: &Path, paths: Vec<PathBuf>) {
+        let first = self.candidates.len();
+        for path in paths {
+            let path = path
+                .strip_prefix(root)
+                .map(Path::to_path_buf)
+                .unwrap_or(path);
+            let chars = path.to_string_lossy().chars().collect();
+            self.candidates.push(Candidate { path, chars });
+        }
+        let mut added: Vec<usize> = (first..self.candidates.len()).collect();
+        let top = self.levels.len() - 1;
+        for (len, level) in self.levels.iter_mut().enumerate() {
+            let prefix = &self.query[..len];
+            let ignore_case = ignores_case(prefix);
+            let candidates = &self.candidates;
+            added.retain(|&entry| is_match(prefix, &candidates[entry].chars, ignore_case));
+            level.matching.extend_from_slice(&added);
+            // Only the level being shown is kept ranked; the others are ranked again if they're returned to
+            if len < top {
+                level.results = None;
+            }
+        }
+        if self.levels[top].results.is_some() {
+            self.rank(top, added);
+        }
+    }
+
+    /// Score the given entries of a level and merge the ones that match into its results.
+    fn rank(&mut self, len: usize, entries: Vec<usize>) {
+        let query = &self.query[..len];
+        let ignore_case = ignores_case(query);
+        let mut results = self.levels[len].results.take().unwrap_or_default();
+        for entry in entries {
+            let candidate = &self.candidates[entry];
+            if let Some((score, positions)) =
+                self.matcher
+                    .fuzzy_match(query, &candidate.chars, ignore_case)
+            {
+                results.push(FinderMatch {
+                    entry,
+                    path: candidate.path.clone(),
+                    score,
+                    positions,
+                });
+            }
+        }
+        // Best first; among equals, shorter paths, then the order they were found in
+        let candidates = &self.candidates;
+        results.sort_by_key(|result| {
+            (
+                std::cmp::Reverse(result.score),
+                candidates[result.entry].chars.len(),
+                result.entry,
+            )
+        });
+        results.truncate(MAX_RESULTS);
+        self.levels[len].results = Some(results);
+    }
+
+    /// The best matches for the whole query
+    fn results(&mut self) -> Vec<FinderMatch> {
+        let top = self.levels.len() - 1;
+        if self.levels[top].results.is_none() {
+            let matching = self.levels[top].matching.clone();
+            self.rank(top, matching);
+        }
+        self.levels[top].results.clone().unwrap_or_default()
+    }
+}
+
+/// Walk `root`, ranking what's found against the newest of `queries`, and send back the best matches until
+/// `cancel` is set or the finder is closed.
+fn work(
+    root: PathBuf,
+    options: FinderOptions,
+    walk: usize,
+    queries: Receiver<String>,
+    events: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+) {
+    let mut walker = WalkBuilder::new(&root)
+        .git_ignore(options.gitignore)
+        .git_global(options.gitignore)
+        .git_exclude(options.gitignore)
+        .ignore(options.gitignore)
+        // Honor .gitignore files in plain directories too
+        .require_git(false)
+        .same_file_system(options.one_file_system)
+        .build();
+    let mut ranker = Ranker::default();
+    let mut walking = true;
+    loop {
+        let mut query = None;
+        if !walking {
+            // Nothing to do until the query changes
+            match queries.recv() {
+                Ok(q) => query = Some(q),
+                Err(_) => return,
+            }
+        }
+        // Queries typed while ranking are already out of date; only the newest is worth ranking for
+        while let Ok(q) = queries.try_recv() {
+            query = Some(q);
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(query) = query {
+            ranker.set_query(&query);
+        }
+
+        if walking {
+            let mut paths = Vec::new();
+            let started = Instant::now();
+            while started.elapsed() < REPORT_INTERVAL {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                match walker.next() {
+                    Some(Ok(entry)) if entry.depth() > 0 => paths.push(entry.into_path()),
+                    // Unreadable directories are skipped, not reported
+                    Some(_) => {}
+                    None => {
+                        walking = false;
+                        break;
+                    }
+                }
+            }
+            ranker.add(&root, paths);
+        }
+
+        let event = FinderEvent {
+            walk,
+            query: ranker.query.iter().collect(),
+            results: ranker.results(),
+            found: ranker.candidates.len(),
+            done: !walking,
+        };
+        if events.send(Event::Finder(event)).is_err() {
+            return;
+        }
+    }
+}
+
+/// The finder overlay's state: the query and the ranked results the worker sent back for it.
+pub struct Finder {
+    events: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+    /// Counts walks, so results from an abandoned walk are recognized
+    walk: usize,
+    /// Where the worker takes the query from; dropped when the finder closes
+    queries: Option<Sender<String>>,
+    pub root: PathBuf,
+    pub options: FinderOptions,
+    found: usize,
+    /// Still walking the tree
+    pub walking: bool,
+    pub query: String,
+    /// The best matches, best first
+    pub results: Vec<FinderMatch>,
+    /// The query `results` were ranked for, which lags behind `query` while the worker catches up
+    ranked: String,
+    pub state: ListState,
+}
+
+impl Finder {
+    /// The worker delivers results through `events`.
+    pub fn new(events: Sender<Event<Key>>) -> Self {
+        Self {
+            events,
+            cancel: Arc::new(AtomicBool::new(false)),
+            walk: 0,
+            queries: None,
+            root: PathBuf::new(),
+            options: FinderOptions::default(),
+            found: 0,
+            walking: false,
+            query: String::new(),
+            results: Vec::new(),
+            ranked: String::new(),
+            state: ListState::default(),
+        }
+    }
+
+    /// Start searching below `root` with an empty query.
+    pub fn open(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+        self.query.clear();
+        self.start();
+    }
+
+    /// Start walking afresh, keeping the query.
+    fn start(&mut self) {
+        self.close();
+        self.cancel = Arc::new(AtomicBool::new(false));
+        self.walk += 1;
+        self.walking = true;
+        self.found = 0;
+        self.results.clear();
+        self.ranked.clear();
+        self.state.select(None);
+        let (queries, rx) = mpsc::channel();
+        self.queries = Some(queries);
+        self.send_query();
+        let (root, options, walk_id) = (self.root.clone(), self.options, self.walk);
+        let (events, cancel) = (self.events.clone(), self.cancel.clone());
+        thread::spawn(move || work(root, options, walk_id, rx, events, cancel));
+    }
+
+    /// Stop the worker, if it's still going.
+    pub fn close(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.queries = None;
+        self.walking = false;
+    }
+
+    pub fn toggle_gitignore(&mut self) {
+        self.options.gitignore = !self.options.gitignore;
+        self.start();
+    }
+
+    pub fn toggle_one_file_system(&mut self) {
+        self.options.one_file_system = !self.options.one_file_system;
+        self.start();
+    }
+
+    /// How many paths have been found so far
+    pub fn found(&self) -> usize {
+        self.found
+    }
+
+    /// The path a result stands for, relative to the root
+    pub fn relative_path<'a>(&self, result: &'a FinderMatch) -> &'a Path {
+        &result.path
+    }
+
+    /// The full path of the selected result
+    pub fn selected(&self) -> Option<PathBuf> {
+        let result = self.results.get(self.state.selected()?)?;
+        Some(self.root.join(self.relative_path(result)))
+    }
+
+    /// Take the worker's latest results.
+    pub fn insert(&mut self, event: FinderEvent) {
+        if event.walk != self.walk {
+            return;
+        }
+        if event.done {
+            self.walking = false;
+        }
+        self.found = event.found;
+        // Results for a query that has since been typed over would only flicker past
+        if event.query != self.query {
+            return;
+        }
+        // The selection stays put while more paths come in, and starts at the top for a new query
+        let selected = match self.state.selected() {
+            Some(selected) if event.query == self.ranked => Some(selected),
+            _ => None,
+        };
+        self.results = event.results;
+        self.ranked = event.query;
+        self.state.select(match selected {
+            _ if self.results.is_empty() => None,
+            Some(selected) => Some(selected.min(self.results.len() - 1)),
+            None => Some(0),
+        });
+    }
+
+    /// Hand the query to the worker to rank for.
+    fn send_query(&self) {
+        if let Some(queries) = &self.queries {
+            // A worker that has gone away has nothing more to rank
+            let _ = queries.send(self.query.clone());
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.send_query();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.send_query();
+    }
+
+    pub fn next(&mut self) {
+        if let Some(selected) = self.state.selected() {
+            if selected + 1 < self.results.len() {
+                self.state.select(Some(selected + 1));
+            }
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if let Some(selected) = self.state.selected() {
+            self.state.select(Some(selected.saturating_sub(1)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn fuzzy_match(query: &str, text: &str, ignore_case: bool) -> Option<(i64, Vec<usize>)> {
+        FuzzyMatcher::default().fuzzy_match(&chars(query), &chars(text), ignore_case)
+    }
+
+    fn score(query: &str, text: &str) -> Option<i64> {
+        fuzzy_match(query, text, true).map(|(score, _)| score)
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(score("xyz", "src/main.rs"), None);
+        assert_eq!(score("nim", "src/main.rs"), None);
+        assert_eq!(
+            fuzzy_match("main", "src/domain/main.rs", true).map(|(_, p)| p),
+            Some(vec![11, 12, 13, 14])
+        );
+        // Word starts and runs beat scattered letters
+        assert!(score("mr", "src/main_rs") > score("mr", "src/amber"));
+        assert!(score("app", "src/app.rs") > score("app", "a/p/p/x.rs"));
+        assert!(score("fb", "FooBar") > score("fb", "fabric"));
+        // Case matters when asked to
+        assert_eq!(fuzzy_match("M", "main", false), None);
+        assert_eq!(score("", "anything"), Some(0));
+    }
+
+    #[test]
+    fn test_ranker_levels() {
+        let paths = |ranker: &mut Ranker| -> Vec<String> {
+            ranker
+                .results()
+                .iter()
+                .map(|result| result.path.to_string_lossy().into_owned())
+                .collect()
+        };
+        let mut ranker = Ranker::default();
+        let root = Path::new("/root");
+        ranker.add(root, vec![root.join("src/main.rs"), root.join("Makefile")]);
+        ranker.set_query("ma");
+        assert_eq!(paths(&mut ranker), vec!["Makefile", "src/main.rs"]);
+        // A capital makes the query case-sensitive, narrowing what the shorter query matched
+        ranker.set_query("maK");
+        assert_eq!(paths(&mut ranker), Vec::<String>::new());
+        ranker.set_query("Ma");
+        assert_eq!(paths(&mut ranker), vec!["Makefile"]);
+
+        // Paths found later reach every level, so deleting a character goes back to the right set
+        ranker.set_query("main");
+        ranker.add(root, vec![root.join("docs/main.md")]);
+        assert_eq!(paths(&mut ranker), vec!["src/main.rs", "docs/main.md"]);
+        ranker.set_query("ma");
+        assert_eq!(
+            paths(&mut ranker),
+            vec!["Makefile", "src/main.rs", "docs/main.md"]
+        );
+        assert_eq!(ranker.levels.len(), 3);
+        ranker.set_query("");
+        assert_eq!(paths(&mut ranker).len(), 3);
+    }
+}
@@ -0,0 +1,784 @@
+//! `fs_ops` implements the filesystem operations behind copying and moving entries.
+//!
+//! File contents are copied byte-for-byte in fixed-size chunks, never loaded whole.  Where the filesystem allows it,
+//! the data is shared with a reflink or copied inside the kernel with `copy_file_range`.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{bail, Context, Result};
+
+// Error code returned by rename(2) across filesystems
+use libc::EXDEV;
+
+use crate::permissions::ModeSpec;
+
+// Input and output, OS integration
+use std::{
+    error,
+    ffi::CString,
+    fmt,
+    fs::{self, canonicalize, File, OpenOptions},
+    io::{self, Read, Write},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Number of bytes moved per `copy_file_range` call or read into memory at once
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Receives progress reports from long-running operations, and can ask them to stop.
+pub trait Progress {
+    /// `n` more bytes of file data have been copied.
+    fn bytes(&mut self, n: u64);
+    /// Another entry has been completely handled.
+    fn entry_done(&mut self);
+    /// Whether the operation should stop at the next opportunity.
+    fn cancelled(&self) -> bool;
+}
+
+/// For operations quick enough to run on the spot, and for tests, which don't track progress.
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn bytes(&mut self, _: u64) {}
+    fn entry_done(&mut self) {}
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// The error returned when a `Progress` cancels an operation
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+/// Whether an error chain ends in a cancellation.
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+    err.root_cause().is::<Cancelled>()
+}
+
+/// Bail out if the operation has been cancelled.
+pub fn check_cancelled(progress: &dyn Progress) -> Result<()> {
+    if progress.cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(())
+}
+
+/// Which attributes a copy carries over from its source, like `cp --preserve`, and whether it follows links.
+/// Without `mode`, new entries get the source's permission bits minus the umask, and no setuid/setgid/sticky bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CopyOptions {
+    pub mode: bool,
+    pub timestamps: bool,
+    pub ownership: bool,
+    pub xattrs: bool,
+    /// Copy what symbolic links point to instead of the links themselves, like `cp -L`
+    pub dereference: bool,
+}
+
+impl CopyOptions {
+    /// Preserve everything, like `cp -a`
+    pub fn archive() -> Self {
+        Self {
+            mode: true,
+            timestamps: true,
+            ownership: true,
+            xattrs: true,
+            dereference: false,
+        }
+    }
+
+    /// Whether every attribute is preserved, whatever happens to links
+    pub fn is_archive(&self) -> bool {
+        Self {
+            dereference: false,
+            ..*self
+        } == Self::archive()
+    }
+}
+
+/// Make a path absolute without following a symlink in its final component.
+pub fn normalize(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            canonicalize(parent)
+                .map(|p| p.join(name))
+                .unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+/// The way from directory `base` to `target`, both absolute, climbing out with `..` where needed.
+pub fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let base: Vec<_> = base.components().collect();
+    let target: Vec<_> = target.components().collect();
+    let common = base.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    let mut result: PathBuf = base[common..].iter().map(|_| "..").collect();
+    result.extend(&target[common..]);
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+    result
+}
+
+/// Convert a path for handing to libc.
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// When an entry was created, if the kernel and filesystem record it.  Symlinks are not followed.
+///
+/// This asks `statx` directly: the standard library only asks for the birth time on some targets.
+pub fn birth_time(path: &Path) -> Option<SystemTime> {
+    let path = c_path(path).ok()?;
+    let mut buf = mem::MaybeUninit::<libc::statx>::zeroed();
+    // SAFETY: `path` is NUL-terminated and `buf` is a `struct statx` for the kernel to fill in.
+    let rc = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+            libc::STATX_BTIME,
+            buf.as_mut_ptr(),
+        )
+    };
+    // SAFETY: zeroed is a valid `struct statx`, whether or not the call filled it in.
+    let buf = unsafe { buf.assume_init() };
+    if rc != 0 || buf.stx_mask & libc::STATX_BTIME == 0 {
+        return None;
+    }
+    let btime = buf.stx_btime;
+    let since_epoch = Duration::new(btime.tv_sec.unsigned_abs(), btime.tv_nsec);
+    if btime.tv_sec >= 0 {
+        UNIX_EPOCH.checked_add(since_epoch)
+    } else {
+        UNIX_EPOCH.checked_sub(since_epoch)
+    }
+}
+
+/// The error from the last failed libc call, naming the operation and path.
+fn os_error(operation: &str, path: &Path) -> anyhow::Error {
+    anyhow::Error::new(io::Error::last_os_error()).context(format!("{} {:?}", operation, path))
+}
+
+/// Try to copy the whole file inside the kernel.  Returns `false` if the filesystems can't do that, before
+/// anything has been written.
+fn copy_in_kernel(src: &File, dst: &File, progress: &mut dyn Progress) -> Result<bool> {
+    // SAFETY: both descriptors stay open for the duration of the call.
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } == 0 {
+        progress.bytes(src.metadata()?.len());
+        return Ok(true);
+    }
+    let mut copied = 0;
+    loop {
+        check_cancelled(progress)?;
+        // SAFETY: null offsets make the kernel use and advance each file's own position.
+        let n = unsafe {
+            libc::syscall(
+                libc::SYS_copy_file_range,
+                src.as_raw_fd(),
+                ptr::null_mut::<libc::loff_t>(),
+                dst.as_raw_fd(),
+                ptr::null_mut::<libc::loff_t>(),
+                CHUNK_SIZE,
+                0,
+            )
+        };
+        if n > 0 {
+            copied += n;
+            progress.bytes(n as u64);
+        } else if n == 0 {
+            // Some pseudo-filesystems report EOF immediately, so let the generic copy double-check
+            return Ok(copied > 0);
+        } else {
+            let err = io::Error::last_os_error();
+            let unsupported = matches!(
+                err.raw_os_error(),
+                Some(libc::ENOSYS)
+                    | Some(libc::EXDEV)
+                    | Some(libc::EINVAL)
+                    | Some(libc::EOPNOTSUPP)
+                    | Some(libc::EPERM)
+                    | Some(libc::EBADF)
+            );
+            if unsupported && copied == 0 {
+                return Ok(false);
+            }
+            return Err(err.into());
+        }
+    }
+}
+
+/// Copy every byte from `src` to `dst`.
+fn copy_contents(src: &mut File, dst: &mut File, progress: &mut dyn Progress) -> Result<()> {
+    if copy_in_kernel(src, dst, progress)? {
+        return Ok(());
+    }
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        check_cancelled(progress)?;
+        let n = match src.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        dst.write_all(&buf[..n])?;
+        progress.bytes(n as u64);
+    }
+}
+
+/// Copy a regular file's contents into a new file at `to`.
+fn copy_file(from: &Path, to: &Path, m: &fs::Metadata, progress: &mut dyn Progress) -> Result<()> {
+    let mut src = File::open(from).with_context(|| format!("opening {:?}", from))?;
+    let mut dst = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(m.mode() & 0o777)
+        .open(to)
+        .with_context(|| format!("creating {:?}", to))?;
+    copy_contents(&mut src, &mut dst, progress).with_context(|| format!("copying {:?}", from))
+}
+
+/// Copy every extended attribute the destination filesystem will accept.
+fn copy_xattrs(from: &Path, to: &Path) -> Result<()> {
+    let (src, dst) = (c_path(from)?, c_path(to)?);
+    // SAFETY: a null buffer of size 0 only asks for the required length.
+    let size = unsafe { libc::llistxattr(src.as_ptr(), ptr::null_mut(), 0) };
+    if size < 0 {
+        return match io::Error::last_os_error().raw_os_error() {
+            Some(libc::ENOTSUP) => Ok(()),
+            _ => Err(os_error("listing attributes of", from)),
+        };
+    }
+    let mut names = vec![0u8; size as usize];
+    // SAFETY: `names` is writable for its whole length.
+    let size = unsafe { libc::llistxattr(src.as_ptr(), names.as_mut_ptr() as *mut _, names.len()) };
+    if size < 0 {
+        return Err(os_error("listing attributes of", from));
+    }
+    for name in names[..size as usize].split(|&b| b == 0) {
+        if name.is_empty() {
+            continue;
+        }
+        let name = CString::new(name)?;
+        // SAFETY: as above, first ask for the length and then fill a buffer of that length.
+        let len = unsafe { libc::lgetxattr(src.as_ptr(), name.as_ptr(), ptr::null_mut(), 0) };
+        if len < 0 {
+            return Err(os_error("reading attributes of", from));
+        }
+        let mut value = vec![0u8; len as usize];
+        let len = unsafe {
+            libc::lgetxattr(
+                src.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut _,
+                value.len(),
+            )
+        };
+        if len < 0 {
+            return Err(os_error("reading attributes of", from));
+        }
+        // SAFETY: `value` holds `len` initialized bytes.
+        let rc = unsafe {
+            libc::lsetxattr(
+                dst.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const _,
+                len as usize,
+                0,
+            )
+        };
+        // Namespaces like `trusted.*` need privileges, and some filesystems don't take attributes at all
+        if rc != 0
+            && !matches!(
+                io::Error::last_os_error().raw_os_error(),
+                Some(libc::ENOTSUP) | Some(libc::EPERM)
+            )
+        {
+            return Err(os_error("setting attributes of", to));
+        }
+    }
+    Ok(())
+}
+
+/// Carry the chosen attributes of `from`, described by `m`, over to the freshly copied `to`.
+fn preserve_attributes(
+    from: &Path,
+    to: &Path,
+    m: &fs::Metadata,
+    options: &CopyOptions,
+) -> Result<()> {
+    let path = c_path(to)?;
+    let is_symlink = m.file_type().is_symlink();
+    if options.ownership {
+        // Only root may give files away, so settle for the group, and failing that keep our own.
+        // This must come before chmod, because chown clears the setuid and setgid bits.
+        // SAFETY: `path` is a valid NUL-terminated string that outlives the calls.
+        let _ = unsafe { libc::lchown(path.as_ptr(), m.uid(), m.gid()) } == 0
+            || unsafe { libc::lchown(path.as_ptr(), u32::MAX, m.gid()) } == 0;
+    }
+    if options.xattrs {
+        copy_xattrs(from, to)?;
+    }
+    if options.mode && !is_symlink {
+        fs::set_permissions(to, fs::Permissions::from_mode(m.mode() & 0o7777))?;
+    }
+    if options.timestamps {
+        let times = [
+            libc::timespec {
+                tv_sec: m.atime(),
+                tv_nsec: m.atime_nsec(),
+            },
+            libc::timespec {
+                tv_sec: m.mtime(),
+                tv_nsec: m.mtime_nsec(),
+            },
+        ];
+        // SAFETY: `times` holds the two entries utimensat reads.
+        let rc = unsafe {
+            libc::utimensat(
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if rc != 0 {
+            return Err(os_error("setting timestamps of", to));
+        }
+    }
+    Ok(())
+}
+
+/// Create a special file (FIFO, socket or device node) with the same type, mode and device number as `m`.
+fn make_node(to: &Path, m: &fs::Metadata) -> Result<()> {
+    let path = c_path(to)?;
+    // SAFETY: `path` is a valid NUL-terminated string that outlives the call.
+    let rc = unsafe { libc::mknod(path.as_ptr(), m.mode(), m.rdev()) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error()).with_context(|| format!("mknod {:?}", to));
+    }
+    Ok(())
+}
+
+/// Copy a single entry, recursing into directories.
+fn copy_entry(
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+    ancestors: &mut Vec<(u64, u64)>,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    check_cancelled(progress)?;
+    let m = if options.dereference {
+        fs::metadata(from)
+    } else {
+        fs::symlink_metadata(from)
+    }
+    .with_context(|| format!("reading {:?}", from))?;
+    let file_type = m.file_type();
+    if file_type.is_dir() {
+        // Followed links can lead back up the tree, or into the copy, which would never end
+        let id = (m.dev(), m.ino());
+        if ancestors.contains(&id) {
+            bail!("{:?} leads back to a directory that is being copied", from);
+        }
+        // Keep the new directory writable for ourselves until its contents are in place
+        fs::DirBuilder::new()
+            .mode(m.mode() & 0o777 | 0o700)
+            .create(to)
+            .with_context(|| format!("creating {:?}", to))?;
+        let created = fs::metadata(to)?;
+        ancestors.push(id);
+        ancestors.push((created.dev(), created.ino()));
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_entry(
+                &entry.path(),
+                &to.join(entry.file_name()),
+                options,
+                ancestors,
+                progress,
+            )?;
+        }
+        ancestors.truncate(ancestors.len() - 2);
+        let missing_owner_bits = 0o700 & !m.mode();
+        if !options.mode && missing_owner_bits != 0 {
+            let created = fs::metadata(to)?.mode() & 0o777;
+            fs::set_permissions(
+                to,
+                fs::Permissions::from_mode(created & !missing_owner_bits),
+            )?;
+        }
+    } else if file_type.is_symlink() {
+        // Recreate the link itself rather than copying whatever it points at
+        symlink(fs::read_link(from)?, to).with_context(|| format!("creating link {:?}", to))?;
+    } else if file_type.is_file() {
+        copy_file(from, to, &m, progress)?;
+    } else if file_type.is_fifo() || file_type.is_socket() {
+        // Reading from a FIFO would block forever, so create a new, empty one instead
+        make_node(to, &m)?;
+    } else if file_type.is_char_device() || file_type.is_block_device() {
+        // Copy the device node, not the device.  This usually requires root.
+        make_node(to, &m)?;
+    } else {
+        bail!("{:?} has an unknown file type", from);
+    }
+    preserve_attributes(from, to, &m, options)?;
+    progress.entry_done();
+    Ok(())
+}
+
+/// Total size of the regular files under `path`, and the number of entries, for reporting progress.
+/// Unreadable parts of the tree are skipped, and the walk stops early once `progress` is cancelled.
+pub fn measure(path: &Path, progress: &dyn Progress) -> (u64, u64) {
+    let m = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return (0, 0),
+    };
+    let mut totals = (if m.is_file() { m.len() } else { 0 }, 1);
+    if m.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            if progress.cancelled() {
+                break;
+            }
+            let (bytes, entries) = measure(&entry.path(), progress);
+            totals.0 += bytes;
+            totals.1 += entries;
+        }
+    }
+    totals
+}
+
+/// Copy `from` to `to`, recursing into directories and carrying over the attributes chosen in `options`.
+/// Symlinks are recreated as links unless `options.dereference` is set, and FIFOs, sockets and device nodes are
+/// recreated as new nodes.
+/// If the copy fails or is cancelled part way, whatever was already written is removed again.
+pub fn copy_path(
+    from: &Path,
+    to: &Path,
+    options: &CopyOptions,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        bail!("{:?} already exists", to);
+    }
+    let source = if options.dereference {
+        canonicalize(from).unwrap_or_else(|_| normalize(from))
+    } else {
+        normalize(from)
+    };
+    if normalize(to).starts_with(source) {
+        bail!("Cannot copy {:?} into itself", from);
+    }
+    let result = copy_entry(from, to, options, &mut Vec::new(), progress);
+    if result.is_err() && fs::symlink_metadata(to).is_ok() {
+        let _ = remove_path(to);
+    }
+    result
+}
+
+/// Delete a path, recursing into directories.
+pub fn remove_path(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Give a freshly created entry the mode `spec` asks for, starting from the mode it was created with.
+/// This sidesteps the umask, as `mkdir -m` does.
+fn apply_mode(path: &Path, spec: Option<&ModeSpec>) -> Result<()> {
+    if let Some(spec) = spec {
+        let m = fs::metadata(path)?;
+        let mode = spec.apply(m.mode(), m.is_dir());
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Create a directory along with any missing parents, like `mkdir -p`.  The mode only applies to the last one.
+/// A directory that already exists is left as it is.
+pub fn make_dir(path: &Path, mode: Option<&ModeSpec>) -> Result<()> {
+    if path.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("creating {:?}", parent))?;
+    }
+    fs::create_dir(path).with_context(|| format!("creating {:?}", path))?;
+    apply_mode(path, mode)
+}
+
+/// Create an empty file along with any missing parent directories, or update the times of an existing one
+/// without touching its contents, like `touch`.  The mode only applies to a new file.  A dangling symbolic link is
+/// an error rather than a way to create its target.
+pub fn touch(path: &Path, mode: Option<&ModeSpec>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("creating {:?}", parent))?;
+    }
+    match OpenOptions::new().append(true).create_new(true).open(path) {
+        Ok(_) => apply_mode(path, mode),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // Set the times by name, which works for directories and read-only files that can't be opened for writing
+            let c = c_path(path)?;
+            // SAFETY: `c` is NUL-terminated, and null times mean now.
+            if unsafe { libc::utimensat(libc::AT_FDCWD, c.as_ptr(), ptr::null(), 0) } != 0 {
+                return Err(os_error("updating the times of", path));
+            }
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("creating {:?}", path)),
+    }
+}
+
+/// Rename `from` to `to`.  When they live on different filesystems, fall back to copy-then-delete,
+/// preserving attributes as `mv` does.  Cancelling leaves the source untouched.
+pub fn move_path(from: &Path, to: &Path, progress: &mut dyn Progress) -> Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        bail!("{:?} already exists", to);
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(EXDEV) => {
+            copy_path(from, to, &CopyOptions::archive(), progress)?;
+            remove_path(from)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A fresh scratch directory for one test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file_utility_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_move_path_refuses_to_overwrite() {
+        let dir = scratch("move");
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        assert!(move_path(&a, &b, &mut NoProgress).is_err());
+        move_path(&a, &c, &mut NoProgress).unwrap();
+        assert!(!a.exists());
+        assert_eq!(fs::read_to_string(&c).unwrap(), "a");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        let dir = scratch("copy_tree");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/file"), "contents").unwrap();
+        symlink("nested/file", src.join("link")).unwrap();
+        let fifo = c_path(&src.join("fifo")).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let dst = dir.join("dst");
+        copy_path(&src, &dst, &CopyOptions::default(), &mut NoProgress).unwrap();
+        assert_eq!(
+            fs::read_to_string(dst.join("nested/file")).unwrap(),
+            "contents"
+        );
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            Path::new("nested/file")
+        );
+        assert!(fs::symlink_metadata(dst.join("fifo"))
+            .unwrap()
+            .file_type()
+            .is_fifo());
+        assert!(copy_path(
+            &src,
+            &src.join("nested/inside"),
+            &CopyOptions::default(),
+            &mut NoProgress
+        )
+        .is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_copy_is_exact() {
+        let dir = scratch("copy_archive");
+        let src = dir.join("src");
+        // Not valid UTF-8, and larger than one chunk
+        let contents: Vec<u8> = (0..CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &contents).unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+        let times = [
+            libc::timespec {
+                tv_sec: 1_000_000_000,
+                tv_nsec: 0,
+            },
+            libc::timespec {
+                tv_sec: 1_234_567_890,
+                tv_nsec: 0,
+            },
+        ];
+        let c_src = c_path(&src).unwrap();
+        assert_eq!(
+            unsafe { libc::utimensat(libc::AT_FDCWD, c_src.as_ptr(), times.as_ptr(), 0) },
+            0
+        );
+
+        let dst = dir.join("dst");
+        copy_path(&src, &dst, &CopyOptions::archive(), &mut NoProgress).unwrap();
+        // Check the times before reading the contents back bumps the access time
+        let m = fs::metadata(&dst).unwrap();
+        assert_eq!(m.mode() & 0o7777, 0o640);
+        assert_eq!(m.mtime(), 1_234_567_890);
+        assert_eq!(m.atime(), 1_000_000_000);
+        assert_eq!(fs::read(&dst).unwrap(), contents);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Cancels as soon as any data has been copied
+    struct CancelAfterFirstChunk(u64);
+
+    impl Progress for CancelAfterFirstChunk {
+        fn bytes(&mut self, n: u64) {
+            self.0 += n;
+        }
+        fn entry_done(&mut self) {}
+        fn cancelled(&self) -> bool {
+            self.0 > 0
+        }
+    }
+
+    #[test]
+    fn test_cancelled_copy_cleans_up() {
+        let dir = scratch("copy_cancel");
+        let src = dir.join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a"), vec![1; CHUNK_SIZE]).unwrap();
+        fs::write(src.join("b"), vec![2; CHUNK_SIZE]).unwrap();
+
+        let dst = dir.join("dst");
+        let err = copy_path(
+            &src,
+            &dst,
+            &CopyOptions::default(),
+            &mut CancelAfterFirstChunk(0),
+        )
+        .unwrap_err();
+        assert!(is_cancelled(&err));
+        assert!(!dst.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_make_dir_and_touch() {
+        let dir = scratch("make");
+        let mode = "750".parse::<ModeSpec>().unwrap();
+        let nested = dir.join("a/b/c");
+        make_dir(&nested, Some(&mode)).unwrap();
+        assert_eq!(fs::metadata(&nested).unwrap().mode() & 0o7777, 0o750);
+        // Already there is fine, and the mode stays as it was
+        make_dir(&nested, None).unwrap();
+        assert_eq!(fs::metadata(&nested).unwrap().mode() & 0o7777, 0o750);
+
+        let file = dir.join("x/file");
+        let mode = "u=rw,go=".parse::<ModeSpec>().unwrap();
+        touch(&file, Some(&mode)).unwrap();
+        assert_eq!(fs::metadata(&file).unwrap().mode() & 0o7777, 0o600);
+        fs::write(&file, "contents").unwrap();
+        touch(&file, None).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "contents");
+        // Directories and read-only files get new times too
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let read_only = dir.join("read-only");
+        fs::write(&read_only, "").unwrap();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o444)).unwrap();
+        for existing in [&nested, &read_only] {
+            File::open(existing)
+                .unwrap()
+                .set_times(fs::FileTimes::new().set_modified(long_ago))
+                .unwrap();
+            touch(existing, None).unwrap();
+            assert!(fs::metadata(existing).unwrap().modified().unwrap() > long_ago);
+        }
+        assert_eq!(fs::metadata(&read_only).unwrap().mode() & 0o7777, 0o444);
+        // The target of a dangling link isn't created behind the mode's back
+        let link = dir.join("dangling");
+        std::os::unix::fs::symlink(dir.join("missing"), &link).unwrap();
+        assert!(touch(&link, Some(&mode)).is_err());
+        assert!(fs::symlink_metadata(dir.join("missing")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let relative = |base, target| relative_path(Path::new(base), Path::new(target));
+        assert_eq!(relative("/a/b", "/a/b/c"), Path::new("c"));
+        assert_eq!(relative("/a/b", "/a/c/d"), Path::new("../c/d"));
+        assert_eq!(relative("/a/b/c", "/x"), Path::new("../../../x"));
+        assert_eq!(relative("/a", "/a"), Path::new("."));
+    }
+
+    #[test]
+    fn test_dereferencing_copy() {
+        let dir = scratch("dereference");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("file"), "contents").unwrap();
+        symlink("file", src.join("link")).unwrap();
+
+        let options = CopyOptions {
+            dereference: true,
+            ..CopyOptions::default()
+        };
+        let dst = dir.join("dst");
+        copy_path(&src, &dst, &options, &mut NoProgress).unwrap();
+        let m = fs::symlink_metadata(dst.join("link")).unwrap();
+        assert!(m.is_file());
+        assert_eq!(fs::read_to_string(dst.join("link")).unwrap(), "contents");
+
+        // A link back up the tree is refused instead of copied forever
+        symlink("..", src.join("up")).unwrap();
+        assert!(copy_path(&src, &dir.join("again"), &options, &mut NoProgress).is_err());
+        assert!(!dir.join("again").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
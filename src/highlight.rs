@@ -0,0 +1,121 @@
+//! `highlight` turns previewed text into styled lines.
+//!
+//! With the `highlight` cargo feature, [syntect](https://github.com/trishume/syntect) picks a syntax from the file
+//! extension or the first line (think of shebangs) and colors the text.  Without it, lines come back unstyled, which
+//! keeps the static build small.
+
+// Styled text for the details pane
+use tui::text::{Span, Spans};
+
+#[cfg(feature = "highlight")]
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+#[cfg(feature = "highlight")]
+use tui::style::{Color, Style};
+
+use std::path::Path;
+
+/// Theme used for highlighting, from syntect's bundled set
+#[cfg(feature = "highlight")]
+const THEME: &str = "base16-ocean.dark";
+
+/// Split text into lines without their line endings.
+fn plain_lines(text: &str) -> Vec<Spans<'static>> {
+    text.lines()
+        .map(|line| Spans::from(Span::raw(line.to_string())))
+        .collect()
+}
+
+/// Holds whatever highlighting needs loaded.  That takes a moment, so keep one around.
+#[cfg(feature = "highlight")]
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+#[cfg(feature = "highlight")]
+impl Highlighter {
+    pub fn new() -> Self {
+        let mut themes = ThemeSet::load_defaults();
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes.themes.remove(THEME).unwrap_or_default(),
+        }
+    }
+
+    /// Style `text` from `path`, whose first line is `first_line`.  Unknown file types come back plain.
+    pub fn lines(&self, path: &Path, first_line: &str, text: &str) -> Vec<Spans<'static>> {
+        let syntax = path
+            .extension()
+            .and_then(|ext| {
+                self.syntaxes
+                    .find_syntax_by_extension(&ext.to_string_lossy())
+            })
+            .or_else(|| self.syntaxes.find_syntax_by_first_line(first_line));
+        let syntax = match syntax {
+            Some(syntax) => syntax,
+            None => return plain_lines(text),
+        };
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        let mut result = Vec::new();
+        for line in LinesWithEndings::from(text) {
+            let regions = match highlighter.highlight_line(line, &self.syntaxes) {
+                Ok(regions) => regions,
+                Err(_) => return plain_lines(text),
+            };
+            let spans = regions
+                .into_iter()
+                .map(|(style, piece)| {
+                    let fg = style.foreground;
+                    Span::styled(
+                        piece.trim_end_matches(&['\n', '\r'][..]).to_string(),
+                        Style::default().fg(Color::Rgb(fg.r, fg.g, fg.b)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            result.push(Spans::from(spans));
+        }
+        result
+    }
+}
+
+/// Without the `highlight` feature there is nothing to load
+#[cfg(not(feature = "highlight"))]
+pub struct Highlighter;
+
+#[cfg(not(feature = "highlight"))]
+impl Highlighter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Split `text` into unstyled lines.
+    pub fn lines(&self, _path: &Path, _first_line: &str, text: &str) -> Vec<Spans<'static>> {
+        plain_lines(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn line_text(spans: &Spans) -> String {
+        spans.0.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn test_lines_keep_text() {
+        let text = "#!/bin/sh\necho \"hi\"\r\n\nexit 0";
+        let lines = Highlighter::new().lines(Path::new("script"), "#!/bin/sh", text);
+        let texts: Vec<String> = lines.iter().map(line_text).collect();
+        assert_eq!(texts, vec!["#!/bin/sh", "echo \"hi\"", "", "exit 0"]);
+        // Recognized from the shebang, so the line is split into differently styled pieces
+        #[cfg(feature = "highlight")]
+        assert!(lines[1].0.len() > 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! `history` remembers the directories visited, for going back and forward like a web browser.
+
+// Where the listing was scrolled to
+use tui::widgets::ListState;
+
+use std::path::PathBuf;
+
+/// Most directories remembered in each direction
+const MAX_VISITS: usize = 100;
+
+/// A directory as it was left: which entry was selected, and how the listing was scrolled
+#[derive(Debug, Clone)]
+pub struct Visit {
+    pub dir: PathBuf,
+    /// The selected entry, found again by path in case the listing changed meanwhile
+    pub selected: Option<PathBuf>,
+    pub state: ListState,
+}
+
+/// Directories to go back and forward to, most recent last
+#[derive(Debug, Default)]
+pub struct History {
+    back: Vec<Visit>,
+    forward: Vec<Visit>,
+}
+
+impl History {
+    /// Remember a directory being left for a new one.  Going somewhere new forgets the way forward.
+    pub fn push(&mut self, visit: Visit) {
+        self.back.push(visit);
+        if self.back.len() > MAX_VISITS {
+            self.back.remove(0);
+        }
+        self.forward.clear();
+    }
+
+    /// Where going back leads.  The step is only taken with `back`, once the directory has been opened.
+    pub fn back_to(&self) -> Option<&Visit> {
+        self.back.last()
+    }
+
+    /// Step back from `current`, which becomes the way forward.
+    pub fn back(&mut self, current: Visit) {
+        if self.back.pop().is_some() {
+            self.forward.push(current);
+        }
+    }
+
+    /// Forget where going back leads, when it can't be opened any more.
+    pub fn forget_back(&mut self) {
+        self.back.pop();
+    }
+
+    /// Where going forward leads.  The step is only taken with `forward`, once the directory has been opened.
+    pub fn forward_to(&self) -> Option<&Visit> {
+        self.forward.last()
+    }
+
+    /// Step forward from `current`, which becomes the way back.
+    pub fn forward(&mut self, current: Visit) {
+        if self.forward.pop().is_some() {
+            self.back.push(current);
+        }
+    }
+
+    /// Forget where going forward leads, when it can't be opened any more.
+    pub fn forget_forward(&mut self) {
+        self.forward.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn visit(dir: &str) -> Visit {
+        Visit {
+            dir: PathBuf::from(dir),
+            selected: None,
+            state: ListState::default(),
+        }
+    }
+
+    fn dir(visit: Option<&Visit>) -> Option<&str> {
+        visit.and_then(|visit| visit.dir.to_str())
+    }
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut history = History::default();
+        assert_eq!(dir(history.back_to()), None);
+        history.back(visit("/a"));
+        assert_eq!(dir(history.forward_to()), None);
+        // Visit /a, then /b, then /c
+        history.push(visit("/a"));
+        history.push(visit("/b"));
+        assert_eq!(dir(history.back_to()), Some("/b"));
+        history.back(visit("/c"));
+        assert_eq!(dir(history.back_to()), Some("/a"));
+        history.back(visit("/b"));
+        assert_eq!(dir(history.back_to()), None);
+        assert_eq!(dir(history.forward_to()), Some("/b"));
+        history.forward(visit("/a"));
+        assert_eq!(dir(history.forward_to()), Some("/c"));
+        // Going somewhere new from /b drops /c
+        history.push(visit("/b"));
+        assert_eq!(dir(history.forward_to()), None);
+        assert_eq!(dir(history.back_to()), Some("/b"));
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut history = History::default();
+        history.push(visit("/a"));
+        history.push(visit("/gone"));
+        // Looking doesn't move, and a directory that can't be opened is dropped without moving either
+        assert_eq!(dir(history.back_to()), Some("/gone"));
+        history.forget_back();
+        assert_eq!(dir(history.back_to()), Some("/a"));
+        assert_eq!(dir(history.forward_to()), None);
+        history.back(visit("/c"));
+        history.forget_forward();
+        assert_eq!(dir(history.forward_to()), None);
+        assert_eq!(dir(history.back_to()), None);
+    }
+}
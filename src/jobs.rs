@@ -0,0 +1,338 @@
+//! `jobs` runs long file operations on worker threads so the interface stays responsive.
+//!
+//! Workers report back through the `Events` channel, and the main loop folds those reports into `Jobs`.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::Result;
+
+// Events are keyed on termion keys
+use termion::event::Key;
+
+use crate::{
+    events::Event,
+    fs_ops::{is_cancelled, measure, Cancelled, Progress},
+    permissions::ChangeSummary,
+};
+
+// Threads and the state they share with the interface
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two progress reports from one worker
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a finished job stays in the jobs panel
+const LINGER: Duration = Duration::from_secs(5);
+
+pub type JobId = usize;
+
+/// Where a job is in its lifecycle
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Running,
+    Done,
+    /// The error, then the causes behind it, outermost first
+    Failed {
+        error: String,
+        causes: Vec<String>,
+    },
+    Cancelled,
+}
+
+/// Whether a job's sources are measured before it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sizing {
+    /// Walk the sources first, so progress can be shown against a total
+    Measure,
+    /// Don't walk anything up front, for work that is usually a single rename.  Progress is shown without a total.
+    Indeterminate,
+}
+
+/// Reports sent from a worker thread to the main loop
+#[derive(Debug)]
+pub enum JobEvent {
+    /// The job's sources have been measured
+    Measured {
+        id: JobId,
+        bytes_total: u64,
+        entries_total: u64,
+    },
+    Progress {
+        id: JobId,
+        bytes_done: u64,
+        entries_done: u64,
+    },
+    Finished {
+        id: JobId,
+        state: JobState,
+        summary: Option<ChangeSummary>,
+    },
+}
+
+/// The worker's handle on its job, passed to file operations as their `Progress`
+pub struct Reporter {
+    id: JobId,
+    tx: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+    bytes_done: u64,
+    entries_done: u64,
+    last_report: Instant,
+    summary: Option<ChangeSummary>,
+}
+
+impl Reporter {
+    /// Hand back what a permission or ownership change did, to be summed up when the job finishes.
+    pub fn summarize(&mut self, summary: ChangeSummary) {
+        self.summary = Some(summary);
+    }
+
+    fn send(&self, event: JobEvent) {
+        // The receiver only goes away when the program is quitting
+        let _ = self.tx.send(Event::Job(event));
+    }
+
+    fn report(&mut self, force: bool) {
+        if force || self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.last_report = Instant::now();
+            self.send(JobEvent::Progress {
+                id: self.id,
+                bytes_done: self.bytes_done,
+                entries_done: self.entries_done,
+            });
+        }
+    }
+}
+
+impl Progress for Reporter {
+    fn bytes(&mut self, n: u64) {
+        self.bytes_done += n;
+        self.report(false);
+    }
+
+    fn entry_done(&mut self) {
+        self.entries_done += 1;
+        self.report(false);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// A job as the interface sees it
+pub struct Job {
+    pub id: JobId,
+    pub description: String,
+    pub state: JobState,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub entries_done: u64,
+    pub entries_total: u64,
+    /// Whether the totals are known yet
+    pub measured: bool,
+    /// Entry to put the cursor on once the job succeeds
+    pub select_on_success: Option<PathBuf>,
+    /// What a permission or ownership change did, once it has finished
+    pub summary: Option<ChangeSummary>,
+    /// The entries the job works on, so listings can look at just those again afterwards
+    pub sources: Vec<PathBuf>,
+    started: Instant,
+    finished: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Job {
+    /// Average bytes per second since the job started
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self
+            .finished
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.started)
+            .as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes_done as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimated time remaining, once there is enough data to guess
+    pub fn eta(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        if self.state != JobState::Running || !self.measured || throughput <= 0.0 {
+            return None;
+        }
+        let remaining = self.bytes_total.saturating_sub(self.bytes_done) as f64;
+        Some(Duration::from_secs_f64(remaining / throughput))
+    }
+}
+
+/// Every job started this session that hasn't been pruned yet
+pub struct Jobs {
+    tx: Sender<Event<Key>>,
+    next_id: JobId,
+    pub list: Vec<Job>,
+}
+
+impl Jobs {
+    pub fn new(tx: Sender<Event<Key>>) -> Self {
+        Self {
+            tx,
+            next_id: 0,
+            list: Vec::new(),
+        }
+    }
+
+    /// Run `work` on `sources` on a new thread, measuring them first if `sizing` asks for it.
+    pub fn spawn<F>(
+        &mut self,
+        description: String,
+        sources: Vec<PathBuf>,
+        sizing: Sizing,
+        select_on_success: Option<PathBuf>,
+        work: F,
+    ) -> JobId
+    where
+        F: FnOnce(&mut Reporter) -> Result<()> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut reporter = Reporter {
+            id,
+            tx: self.tx.clone(),
+            cancel: Arc::clone(&cancel),
+            bytes_done: 0,
+            entries_done: 0,
+            last_report: Instant::now(),
+            summary: None,
+        };
+        let measured = sources.clone();
+        let handle = thread::spawn(move || {
+            if sizing == Sizing::Measure {
+                let (bytes_total, entries_total) = measured
+                    .iter()
+                    .map(|source| measure(source, &reporter))
+                    .fold((0, 0), |acc, (b, e)| (acc.0 + b, acc.1 + e));
+                reporter.send(JobEvent::Measured {
+                    id,
+                    bytes_total,
+                    entries_total,
+                });
+            }
+            let result = if reporter.cancelled() {
+                Err(Cancelled.into())
+            } else {
+                work(&mut reporter)
+            };
+            let state = match result {
+                Ok(()) => JobState::Done,
+                Err(e) if is_cancelled(&e) => JobState::Cancelled,
+                Err(e) => JobState::Failed {
+                    error: e.to_string(),
+                    causes: e.chain().skip(1).map(|cause| cause.to_string()).collect(),
+                },
+            };
+            reporter.report(true);
+            let summary = reporter.summary.take();
+            reporter.send(JobEvent::Finished { id, state, summary });
+        });
+        self.list.push(Job {
+            id,
+            description,
+            state: JobState::Running,
+            bytes_done: 0,
+            bytes_total: 0,
+            entries_done: 0,
+            entries_total: 0,
+            measured: false,
+            select_on_success,
+            summary: None,
+            sources,
+            started: Instant::now(),
+            finished: None,
+            cancel,
+            handle: Some(handle),
+        });
+        id
+    }
+
+    /// Fold a worker's report into the job list.  Returns the job if this report says it finished.
+    pub fn update(&mut self, event: JobEvent) -> Option<&Job> {
+        let id = match event {
+            JobEvent::Measured { id, .. }
+            | JobEvent::Progress { id, .. }
+            | JobEvent::Finished { id, .. } => id,
+        };
+        let job = self.list.iter_mut().find(|job| job.id == id)?;
+        match event {
+            JobEvent::Measured {
+                bytes_total,
+                entries_total,
+                ..
+            } => {
+                job.bytes_total = bytes_total;
+                job.entries_total = entries_total;
+                job.measured = true;
+                None
+            }
+            JobEvent::Progress {
+                bytes_done,
+                entries_done,
+                ..
+            } => {
+                job.bytes_done = bytes_done;
+                job.entries_done = entries_done;
+                None
+            }
+            JobEvent::Finished { state, summary, .. } => {
+                job.state = state;
+                job.summary = summary;
+                job.finished = Some(Instant::now());
+                if let Some(handle) = job.handle.take() {
+                    let _ = handle.join();
+                }
+                Some(job)
+            }
+        }
+    }
+
+    /// Ask the most recently started job that is still running to stop.
+    pub fn cancel_latest(&mut self) {
+        if let Some(job) = self
+            .list
+            .iter()
+            .rev()
+            .find(|job| job.state == JobState::Running)
+        {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Forget jobs that finished a while ago.
+    pub fn prune(&mut self) {
+        self.list
+            .retain(|job| job.finished.is_none_or(|at| at.elapsed() < LINGER));
+    }
+
+    /// Cancel everything still running and wait for the workers to clean up after themselves.
+    pub fn shutdown(&mut self) {
+        for job in &self.list {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        for job in &mut self.list {
+            if let Some(handle) = job.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
@@ -15,20 +15,49 @@ use tui::{backend::TermionBackend, Terminal};
 // IO
 use std::{
     io::{self, stderr, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 // Application state
 mod app;
+// Going back and forward between visited directories
+mod history;
+// Syntax highlighting for previewed text
+mod highlight;
+// File operations running on worker threads
+mod jobs;
+// Fuzzy finding files below the current directory
+mod finder;
+// Narrowing the directory listing by name
+mod filter;
+// Copying and moving files and directory trees
+mod fs_ops;
 // User input event handling.  Largely from TUI-RS documentation.
 mod events;
 // List data structure that tracks extra state.  Largely from TUI-RS documentation.
 mod stateful_list;
+// Notifications and the message log
+mod messages;
+// Parsing and formatting mode bits
+mod permissions;
+// Reading file contents for the details pane
+mod preview;
+// Finding files by their metadata
+mod query;
+// Searching file contents
+mod search;
+// Ordering the directory listing
+mod sort;
+// freedesktop.org trash can
+mod trash;
 // User interface definition
 mod ui;
+// User and group names, and changing ownership
+mod users;
 
-use app::App;
+use app::{App, AppMode, InputType};
 use events::{Event, Events};
+use search::SearchOptions;
 
 /// Print an error that occurred as well as any errors that were chained to get there.
 fn print_error(err: Error) {
@@ -38,6 +67,219 @@ fn print_error(err: Error) {
     }
 }
 
+/// React to one key press.  Returns `Ok(false)` when the user asks to quit.
+///
+/// 1. `q` quits.
+/// 2. The `up`/`down` keys change the currently selected item in the App's `items` list.
+/// 3. `left` unselects the current item.
+/// 4. `right` enters the currently selected directory, or takes no action on files.
+/// 5. Other letters open a text prompt for the matching file operation.
+fn handle_input(app: &mut App, input: Key) -> Result<bool> {
+    match app.mode {
+        AppMode::Nav => match input {
+            Key::Char('q') => return Ok(false),
+            Key::Left | Key::Char('a') => app.dir_list.unselect(),
+            Key::Right | Key::Char('d') | Key::Char('\n') => app.enter_selected()?,
+            Key::Down | Key::Char('s') => app.dir_list.next(),
+            Key::Up | Key::Char('w') => app.dir_list.previous(),
+            Key::Char('p') => app.mode = AppMode::Input(InputType::Permission),
+            Key::Char('P') => app.open_permission_editor()?,
+            Key::Char('R') => app.mode = AppMode::Input(InputType::RecursivePermission),
+            Key::Char('C') => app.mode = AppMode::Input(InputType::Ownership { recursive: false }),
+            Key::Char('O') => app.mode = AppMode::Input(InputType::Ownership { recursive: true }),
+            Key::Char('c') => app.mode = AppMode::Input(InputType::CopyFile),
+            Key::Char('A') => app.toggle_archive(),
+            Key::Char('L') => app.toggle_dereference(),
+            Key::Char('o') => app.change_sort(|order| order.key = order.key.next())?,
+            Key::Char('r') => app.change_sort(|order| order.descending = !order.descending)?,
+            Key::Char('z') => app.change_sort(|order| order.dirs_first = !order.dirs_first)?,
+            Key::Char('.') => app.toggle_hidden(),
+            Key::Char('/') => app.open_filter(),
+            Key::Ctrl('p') => app.open_finder(),
+            Key::Char('G') => app.open_search_prompt(),
+            Key::Char('T') => app.open_search_results(),
+            Key::Char('?') => app.mode = AppMode::Input(InputType::Query),
+            Key::Char('b') => app.go_back()?,
+            Key::Char('B') => app.go_forward()?,
+            Key::Char('f') => app.follow_link()?,
+            Key::Char('F') => app.goto_link_dir()?,
+            Key::Char('S') => app.mode = AppMode::Input(InputType::Symlink { relative: true }),
+            Key::Char('H') => app.mode = AppMode::Input(InputType::HardLink),
+            Key::Char('n') => app.mode = AppMode::Input(InputType::NewFile),
+            Key::Char('N') => app.mode = AppMode::Input(InputType::NewDirectory),
+            Key::Char('m') => app.mode = AppMode::Input(InputType::MoveFile),
+            Key::Char('j') => app.mode = AppMode::Input(InputType::ChangeDir),
+            Key::Char('x') => app.trash_selected()?,
+            Key::Char('t') => app.open_trash()?,
+            Key::Char('k') => app.jobs.cancel_latest(),
+            Key::Char(' ') => app.toggle_mark(),
+            Key::Char('*') => app.mark_all(),
+            Key::Char('i') => app.invert_marks(),
+            Key::Char('u') => app.marked.clear(),
+            Key::Char('l') => app.mode = AppMode::Log,
+            // Esc first hides the notification, then leaves query results
+            Key::Esc if app.messages.current().is_none() && app.query.is_some() => {
+                app.close_query()?
+            }
+            Key::Esc => app.messages.dismiss(),
+            Key::PageDown => app.preview.page_down(),
+            Key::PageUp => app.preview.page_up(),
+            Key::Char('J') => app.preview.scroll_down(),
+            Key::Char('K') => app.preview.scroll_up(),
+            Key::Char('h') => app.preview.cycle_view(),
+            Key::Char('#') => app.preview.toggle_line_numbers(),
+            Key::Char('W') => app.preview.toggle_wrap(),
+            Key::Char('g') => app.mode = AppMode::Input(InputType::GotoOffset),
+            _ => {} // Ignore all other key inputs
+        },
+        AppMode::Log => match input {
+            Key::Char('q') => return Ok(false),
+            Key::Down | Key::Char('s') => app.messages.scroll_down(),
+            Key::Up | Key::Char('w') => app.messages.scroll_up(),
+            Key::Esc | Key::Char('l') => app.mode = AppMode::Nav,
+            _ => {}
+        },
+        AppMode::Trash => match input {
+            Key::Char('q') => return Ok(false),
+            Key::Down | Key::Char('s') => app.trash_list.next(),
+            Key::Up | Key::Char('w') => app.trash_list.previous(),
+            Key::Char('r') => app.restore_selected_trash()?,
+            Key::Char('X') => app.purge_selected_trash()?,
+            Key::Esc | Key::Char('t') => app.mode = AppMode::Nav,
+            _ => {}
+        },
+        AppMode::Search => match input {
+            Key::Char('q') => return Ok(false),
+            Key::Down | Key::Char('s') => app.next_search_hit(),
+            Key::Up | Key::Char('w') => app.previous_search_hit(),
+            Key::Char('\n') => app.goto_search_hit()?,
+            Key::Char('k') => app.search.stop(),
+            Key::Char('G') => app.open_search_prompt(),
+            Key::PageDown => app.preview.page_down(),
+            Key::PageUp => app.preview.page_up(),
+            Key::Char('J') => app.preview.scroll_down(),
+            Key::Char('K') => app.preview.scroll_up(),
+            Key::Char('#') => app.preview.toggle_line_numbers(),
+            Key::Char('W') => app.preview.toggle_wrap(),
+            Key::Esc | Key::Char('T') => app.mode = AppMode::Nav,
+            _ => {}
+        },
+        // Letters go to the query, so only the arrow keys move
+        AppMode::Finder => match input {
+            Key::Up => app.finder.previous(),
+            Key::Down => app.finder.next(),
+            Key::Char('\n') => app.goto_found()?,
+            Key::Char('\t') => app.finder.toggle_one_file_system(),
+            Key::Ctrl('g') => app.finder.toggle_gitignore(),
+            Key::Char(c) => app.finder.push(c),
+            Key::Backspace => app.finder.pop(),
+            Key::Esc => {
+                app.finder.close();
+                app.mode = AppMode::Nav;
+            }
+            _ => {}
+        },
+        AppMode::PermissionEditor => match input {
+            Key::Char('q') => return Ok(false),
+            Key::Up | Key::Char('w') => app.permission_editor.up(),
+            Key::Down | Key::Char('s') => app.permission_editor.down(),
+            Key::Left | Key::Char('a') => app.permission_editor.left(),
+            Key::Right | Key::Char('d') => app.permission_editor.right(),
+            Key::Char(' ') => app.permission_editor.toggle(),
+            Key::Char('\n') => app.apply_permission_editor()?,
+            Key::Esc => app.mode = AppMode::Nav,
+            _ => {}
+        },
+        AppMode::Input(input_type) => match input {
+            Key::Char('\n') => {
+                // Stay in the prompt while the input is invalid; the prompt shows why
+                if app.validate_input(input_type).is_err() {
+                    return Ok(true);
+                }
+                let user_input = app.user_input.drain(..).collect::<String>();
+                // Leave the prompt even if the operation fails
+                app.mode = AppMode::default();
+                match input_type {
+                    InputType::CopyFile => {
+                        app.copy_selected(PathBuf::from(&user_input).as_path())?
+                    }
+                    InputType::MoveFile => {
+                        app.move_selected(PathBuf::from(&user_input).as_path())?
+                    }
+                    InputType::ChangeDir => app.jump_to(PathBuf::from(&user_input).as_path())?,
+                    InputType::Permission => app.set_permissions(&user_input, false)?,
+                    InputType::RecursivePermission => app.set_permissions(&user_input, true)?,
+                    InputType::Ownership { recursive } => {
+                        app.set_ownership(&user_input, recursive)?
+                    }
+                    InputType::GotoOffset => app.goto_offset(&user_input)?,
+                    InputType::Symlink { relative } => {
+                        app.symlink_selected(Path::new(&user_input), relative)?
+                    }
+                    InputType::HardLink => app.hard_link_selected(Path::new(&user_input))?,
+                    InputType::NewFile => app.new_file(&user_input)?,
+                    InputType::NewDirectory => app.new_directory(&user_input)?,
+                    // Already applied while typing
+                    InputType::Filter { .. } => {}
+                    InputType::Search(options) => app.start_search(&user_input, options)?,
+                    InputType::Query => app.start_query(&user_input)?,
+                }
+            }
+            Key::Char('\t') => match input_type {
+                // Tab switches symbolic links between relative and absolute
+                InputType::Symlink { relative } => {
+                    app.mode = AppMode::Input(InputType::Symlink {
+                        relative: !relative,
+                    });
+                }
+                // and filters between substrings, globs and regular expressions
+                InputType::Filter { kind } => {
+                    app.mode = AppMode::Input(InputType::Filter { kind: kind.next() });
+                    app.update_filter(kind.next());
+                }
+                // and searches between fixed strings and regular expressions
+                InputType::Search(options) => {
+                    app.mode = AppMode::Input(InputType::Search(SearchOptions {
+                        regex: !options.regex,
+                        ..options
+                    }));
+                }
+                _ => {}
+            },
+            // Shift-Tab cycles through the ways of treating letter case in searches
+            Key::BackTab => {
+                if let InputType::Search(options) = input_type {
+                    app.mode = AppMode::Input(InputType::Search(SearchOptions {
+                        case: options.case.next(),
+                        ..options
+                    }));
+                }
+            }
+            Key::Char(c) => {
+                app.user_input.push(c);
+                if let InputType::Filter { kind } = input_type {
+                    app.update_filter(kind);
+                }
+            }
+            Key::Backspace => {
+                app.user_input.pop();
+                if let InputType::Filter { kind } = input_type {
+                    app.update_filter(kind);
+                }
+            }
+            Key::Esc => {
+                let _ = app.user_input.drain(..);
+                app.mode = AppMode::Nav;
+                if let InputType::Filter { .. } = input_type {
+                    app.clear_filter();
+                }
+            }
+            _ => {}
+        },
+    }
+    Ok(true)
+}
+
 // Error-checked entry-point
 fn run() -> Result<()> {
     // Grab a handle to STDOUT in raw mode (no auto printing or buffering)
@@ -54,61 +296,53 @@ fn run() -> Result<()> {
 
     // Init event stream and app state
     let events = Events::new();
-    let mut app = App::new();
+    let mut app = App::new(events.sender());
 
     // Render the app.  Runs forever, or until a "quit" event is received.
     // The full widget graph is re-built on every frame.
     loop {
         terminal.draw(|f| ui::draw(f, &mut app))?;
 
-        // 1. This breaks the loop and exits the program on `q` button press.
-        // 2. The `up`/`down` keys change the currently selected item in the App's `items` list.
-        // 3. `left` unselects the current item.
-        // 4. `right` enters the currently selected directory, or takes no action on files.
-        if let Event::Input(input) = events.next()? {
-            use app::{AppMode, InputType};
-            match app.mode {
-                AppMode::Nav => match input {
-                    Key::Char('q') => break,
-                    Key::Left | Key::Char('a') => app.dir_list.unselect(),
-                    Key::Right | Key::Char('d') | Key::Char('\n') => app.enter_selected()?,
-                    Key::Down | Key::Char('s') => app.dir_list.next(),
-                    Key::Up | Key::Char('w') => app.dir_list.previous(),
-                    Key::Char('p') => app.mode = AppMode::Input(InputType::Permission),
-                    Key::Char('c') => app.mode = AppMode::Input(InputType::CopyFile),
-                    Key::Char('j') => app.mode = AppMode::Input(InputType::ChangeDir),
-                    _ => {} // Ignore all other key inputs
-                },
-                AppMode::Input(input_type) => match input {
-                    Key::Char('\n') => {
-                        let user_input = app.user_input.drain(..).collect::<String>();
-                        match input_type {
-                            InputType::CopyFile => {
-                                app.copy_selected(PathBuf::from(&user_input).as_path())?
-                            }
-                            InputType::ChangeDir => {
-                                app.change_dir(PathBuf::from(&user_input).as_path())?
-                            }
-                            InputType::Permission => app.set_permissions(&user_input)?,
-                        }
-                        app.mode = AppMode::default();
-                    }
-                    Key::Char(c) => {
-                        app.user_input.push(c);
-                    }
-                    Key::Backspace => {
-                        app.user_input.pop();
-                    }
-                    Key::Esc => {
-                        let _ = app.user_input.drain(..);
-                        app.mode = AppMode::Nav;
-                    }
-                    _ => {}
-                },
+        // Background jobs report progress as their own events, and old finished jobs are cleared on each tick.
+        // Errors from handling input are shown in the interface rather than ending the session.
+        let input = match events.next()? {
+            Event::Input(input) => input,
+            Event::Job(event) => {
+                if let Err(e) = app.handle_job_event(event) {
+                    app.messages.error(&e);
+                }
+                continue;
+            }
+            Event::Preview(event) => {
+                app.preview.insert(event);
+                continue;
+            }
+            Event::Finder(event) => {
+                app.finder.insert(event);
+                continue;
+            }
+            Event::Search(event) => {
+                app.insert_search_hits(event);
+                continue;
+            }
+            Event::Query(event) => {
+                app.insert_query_matches(event);
+                continue;
+            }
+            Event::Tick => {
+                app.jobs.prune();
+                continue;
             }
+        };
+        match handle_input(&mut app, input) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => app.messages.error(&e),
         }
     }
 
+    // Stop any jobs still running, letting them clean up partial copies
+    app.jobs.shutdown();
     Ok(())
 }
 
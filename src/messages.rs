@@ -0,0 +1,92 @@
+//! `messages` keeps the notifications shown to the user, and a log of every one of them.
+//!
+//! Errors from file operations end up here instead of ending the session.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::Error;
+
+// DateTime handling
+use chrono::prelude::{DateTime, Local};
+
+/// How serious a message is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Level::Info => "Info",
+            Level::Warning => "Warning",
+            Level::Error => "Error",
+        }
+    }
+}
+
+/// A single notification
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    /// The causes behind an error, outermost first
+    pub causes: Vec<String>,
+    pub time: DateTime<Local>,
+}
+
+/// Every message this session, plus which one is currently shown
+#[derive(Debug, Default)]
+pub struct MessageLog {
+    pub entries: Vec<Message>,
+    /// Index of the message in the notification area, until it is dismissed
+    pub current: Option<usize>,
+    /// How many lines the log view is scrolled down
+    pub scroll: u16,
+}
+
+impl MessageLog {
+    /// Add a message and show it in the notification area.
+    pub fn push(&mut self, level: Level, text: String, causes: Vec<String>) {
+        self.entries.push(Message {
+            level,
+            text,
+            causes,
+            time: Local::now(),
+        });
+        self.current = Some(self.entries.len() - 1);
+    }
+
+    pub fn info(&mut self, text: String) {
+        self.push(Level::Info, text, vec![]);
+    }
+
+    pub fn warning(&mut self, text: String) {
+        self.push(Level::Warning, text, vec![]);
+    }
+
+    /// Record an error along with the chain of errors that caused it, like `print_error` does on exit.
+    pub fn error(&mut self, err: &Error) {
+        let causes = err.chain().skip(1).map(|cause| cause.to_string()).collect();
+        self.push(Level::Error, err.to_string(), causes);
+    }
+
+    /// The message in the notification area, if any
+    pub fn current(&self) -> Option<&Message> {
+        self.current.map(|idx| &self.entries[idx])
+    }
+
+    /// Hide the notification.  It stays in the log.
+    pub fn dismiss(&mut self) {
+        self.current = None;
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_add(1);
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+}
@@ -0,0 +1,683 @@
+//! `permissions` parses and formats Unix mode bits.
+//!
+//! Modes can be given the three ways `chmod` and `ls` show them: octal (`0755`), symbolic clauses relative to the
+//! current mode (`u+x,go-w`), or the nine-character `rwsr-xr-x` form of `ls -l`.  All twelve bits are covered,
+//! including setuid, setgid and sticky.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{anyhow, Error, Result};
+
+use crate::fs_ops::{check_cancelled, Progress};
+
+// For parsing/serializing file permissions
+use libc::{
+    S_IRGRP, S_IROTH, S_IRUSR, S_ISGID, S_ISUID, S_ISVTX, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP,
+    S_IXOTH, S_IXUSR,
+};
+
+// Walking directory trees
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt, // Unix-specific st_mode
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+/// The permission bits `chmod` can change
+pub const MODE_MASK: u32 = 0o7777;
+
+/// The process umask, read the first time a mode without a class is parsed
+fn process_umask() -> u32 {
+    static UMASK: OnceLock<u32> = OnceLock::new();
+    *UMASK.get_or_init(|| {
+        // There is no way to read the umask without setting it, so put it straight back
+        let mask = unsafe { libc::umask(0) };
+        unsafe { libc::umask(mask) };
+        mask as u32 & 0o777
+    })
+}
+
+/// The bits of one class of users, and the letter `ls` shows for its special bit
+struct Class {
+    read: u32,
+    write: u32,
+    execute: u32,
+    special: u32,
+    letter: char,
+}
+
+/// User, group and other, in `ls` order
+const CLASSES: [Class; 3] = [
+    Class {
+        read: S_IRUSR,
+        write: S_IWUSR,
+        execute: S_IXUSR,
+        special: S_ISUID,
+        letter: 's',
+    },
+    Class {
+        read: S_IRGRP,
+        write: S_IWGRP,
+        execute: S_IXGRP,
+        special: S_ISGID,
+        letter: 's',
+    },
+    Class {
+        read: S_IROTH,
+        write: S_IWOTH,
+        execute: S_IXOTH,
+        special: S_ISVTX,
+        letter: 't',
+    },
+];
+
+/// Pretty-print a permissions triplet into a human-readable string component.
+/// The special bit shows in the execute column: lowercase over execute, uppercase without it.
+fn triplet(mode: u32, class: &Class) -> String {
+    let flag = |bit, c| if mode & bit != 0 { c } else { '-' };
+    let execute = match (mode & class.execute != 0, mode & class.special != 0) {
+        (false, false) => '-',
+        (true, false) => 'x',
+        (true, true) => class.letter,
+        (false, true) => class.letter.to_ascii_uppercase(),
+    };
+    [flag(class.read, 'r'), flag(class.write, 'w'), execute]
+        .iter()
+        .collect()
+}
+
+/// Parse an st_mode bitfield into a human-readable permission string, as `ls -l` shows it
+pub fn permissions_to_string(mode: u32) -> String {
+    CLASSES.iter().map(|class| triplet(mode, class)).collect()
+}
+
+/// Get the mode value from a `rwxrwxrwx` permission string, with `-` for each missing bit and `s`/`S`/`t`/`T`
+/// for the special bits
+pub fn string_to_permissions(s: &str) -> Option<u32> {
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() != 9 {
+        return None;
+    }
+    chars
+        .chunks(3)
+        .zip(CLASSES.iter())
+        .try_fold(0, |mut acc, (triple, class)| {
+            acc |= match triple[0] {
+                'r' => class.read,
+                '-' => 0,
+                _ => return None,
+            };
+            acc |= match triple[1] {
+                'w' => class.write,
+                '-' => 0,
+                _ => return None,
+            };
+            acc |= match triple[2] {
+                'x' => class.execute,
+                '-' => 0,
+                c if c == class.letter => class.execute | class.special,
+                c if c == class.letter.to_ascii_uppercase() => class.special,
+                _ => return None,
+            };
+            Some(acc)
+        })
+}
+
+/// Why a mode deserves a second look, if it does
+pub fn mode_warning(mode: u32, is_dir: bool) -> Option<&'static str> {
+    if !is_dir && mode & S_ISUID != 0 && mode & S_IWOTH != 0 {
+        Some("setuid on a world-writable file lets anyone run their own code as its owner")
+    } else {
+        None
+    }
+}
+
+/// Both forms of a mode, e.g. `rwxr-xr-x (0755)`
+pub fn describe_mode(mode: u32) -> String {
+    format!("{} ({:04o})", permissions_to_string(mode), mode & MODE_MASK)
+}
+
+/// How one symbolic action changes the bits it covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Remove,
+    Set,
+}
+
+/// The bits named on the right of a symbolic action
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perms {
+    /// Literal `r`, `w` and `x` bits, plus whether `X` was given
+    Bits { bits: u32, exec_if_any: bool },
+    /// The rwx bits another class currently has, as in `g=u`, given as that class's shift
+    Copy(u32),
+}
+
+/// A single `+`, `-` or `=` step of a clause
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Action {
+    op: Op,
+    perms: Perms,
+}
+
+/// One comma-separated part of a symbolic mode, like `go-w`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    /// The rwx and special bits of the classes the clause applies to
+    who: u32,
+    actions: Vec<Action>,
+}
+
+/// A mode as typed by the user, to be applied to each file's current mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModeSpec {
+    /// Replaces the whole mode
+    Absolute(u32),
+    /// Changes the current mode
+    Symbolic(Vec<Clause>),
+}
+
+impl Clause {
+    /// Parse `[ugoa]*([-+=]([rwxXst]*|[ugo]))+`.  Without a class, the clause applies to everyone, but leaves the
+    /// bits set in `umask` alone, as with chmod.
+    fn parse(s: &str, umask: u32) -> Option<Self> {
+        let mut chars = s.chars().peekable();
+        let mut who = 0;
+        while let Some(&c) = chars.peek() {
+            who |= match c {
+                'u' => S_ISUID | 0o700,
+                'g' => S_ISGID | 0o070,
+                'o' => S_ISVTX | 0o007,
+                'a' => MODE_MASK,
+                _ => break,
+            };
+            chars.next();
+        }
+        if who == 0 {
+            who = MODE_MASK & !umask;
+        }
+
+        let mut actions = Vec::new();
+        while let Some(c) = chars.next() {
+            let op = match c {
+                '+' => Op::Add,
+                '-' => Op::Remove,
+                '=' => Op::Set,
+                _ => return None,
+            };
+            let copy = match chars.peek() {
+                Some('u') => Some(6),
+                Some('g') => Some(3),
+                Some('o') => Some(0),
+                _ => None,
+            };
+            let perms = match copy {
+                Some(shift) => {
+                    chars.next();
+                    Perms::Copy(shift)
+                }
+                None => {
+                    let mut bits = 0;
+                    let mut exec_if_any = false;
+                    while let Some(&c) = chars.peek() {
+                        match c {
+                            'r' => bits |= 0o444,
+                            'w' => bits |= 0o222,
+                            'x' => bits |= 0o111,
+                            'X' => exec_if_any = true,
+                            's' => bits |= S_ISUID | S_ISGID,
+                            't' => bits |= S_ISVTX,
+                            _ => break,
+                        }
+                        chars.next();
+                    }
+                    Perms::Bits { bits, exec_if_any }
+                }
+            };
+            actions.push(Action { op, perms });
+        }
+        if actions.is_empty() {
+            None
+        } else {
+            Some(Self { who, actions })
+        }
+    }
+
+    fn apply(&self, mut mode: u32, is_dir: bool) -> u32 {
+        for action in &self.actions {
+            let bits = match action.perms {
+                Perms::Bits { bits, exec_if_any } => {
+                    // `X` only grants execute to directories and files someone can already execute
+                    if exec_if_any && (is_dir || mode & 0o111 != 0) {
+                        bits | 0o111
+                    } else {
+                        bits
+                    }
+                }
+                Perms::Copy(shift) => {
+                    // Only the rwx bits are copied, as with chmod
+                    let class = (mode >> shift) & 0o7;
+                    class << 6 | class << 3 | class
+                }
+            } & self.who;
+            mode = match action.op {
+                Op::Add => mode | bits,
+                Op::Remove => mode & !bits,
+                Op::Set => mode & !self.who | bits,
+            };
+        }
+        mode
+    }
+}
+
+impl ModeSpec {
+    /// The mode a file or directory currently at `current` ends up with
+    pub fn apply(&self, current: u32, is_dir: bool) -> u32 {
+        match self {
+            ModeSpec::Absolute(mode) => *mode,
+            ModeSpec::Symbolic(clauses) => {
+                clauses.iter().fold(current & MODE_MASK, |mode, clause| {
+                    clause.apply(mode, is_dir)
+                })
+            }
+        }
+    }
+}
+
+impl ModeSpec {
+    /// Parse a mode, with clauses that name no class masked by `umask`
+    fn parse(s: &str, umask: u32) -> Result<Self> {
+        let s = s.trim();
+        if !s.is_empty() && s.chars().all(|c| c.is_digit(8)) {
+            return match u32::from_str_radix(s, 8) {
+                Ok(mode) if mode <= MODE_MASK => Ok(ModeSpec::Absolute(mode)),
+                _ => Err(anyhow!("Octal mode {} is out of range", s)),
+            };
+        }
+        if let Some(mode) = string_to_permissions(s) {
+            return Ok(ModeSpec::Absolute(mode));
+        }
+        s.split(',')
+            .map(|clause| {
+                Clause::parse(clause, umask).ok_or_else(|| {
+                    anyhow!("Invalid mode {:?}: try 644, u+x,go-w or rw-r--r--", clause)
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(ModeSpec::Symbolic)
+    }
+}
+
+impl FromStr for ModeSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s, process_umask())
+    }
+}
+
+/// Modes for files and for directories, typed as `file mode[:directory mode]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    pub files: ModeSpec,
+    pub dirs: ModeSpec,
+}
+
+/// What a permission or ownership change did, for the summary message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeSummary {
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Each failure as `path: error`
+    pub failed: Vec<String>,
+    /// Modes worth a second look that were set anyway
+    pub warnings: Vec<String>,
+}
+
+impl ModeChange {
+    /// The spec for an entry of the given kind
+    pub fn spec(&self, is_dir: bool) -> &ModeSpec {
+        if is_dir {
+            &self.dirs
+        } else {
+            &self.files
+        }
+    }
+
+    /// Change the mode of `path` and, if `recursive`, of everything below it.  Directories are changed before
+    /// their contents, so a mode that opens one up takes effect before it is read.  As with chmod, a symbolic link
+    /// given as `path` changes what it points to, while links met inside the tree are left alone.  Failures are
+    /// collected in `summary`; only cancelling stops the walk with an error.
+    pub fn apply(
+        &self,
+        path: &Path,
+        recursive: bool,
+        summary: &mut ChangeSummary,
+        progress: &mut dyn Progress,
+    ) -> Result<()> {
+        self.change(path, true, recursive, summary, progress)
+    }
+
+    /// `apply`, following `path` if it is a link only when `follow` is set
+    fn change(
+        &self,
+        path: &Path,
+        follow: bool,
+        recursive: bool,
+        summary: &mut ChangeSummary,
+        progress: &mut dyn Progress,
+    ) -> Result<()> {
+        check_cancelled(progress)?;
+        let metadata = if follow {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        };
+        let m = match metadata {
+            Ok(m) => m,
+            Err(e) => {
+                summary.failed.push(format!("{}: {}", path.display(), e));
+                return Ok(());
+            }
+        };
+        entry_walked(&m, progress);
+        if m.file_type().is_symlink() {
+            return Ok(());
+        }
+        let old = m.permissions().mode() & MODE_MASK;
+        let new = self.spec(m.is_dir()).apply(old, m.is_dir());
+        if new == old {
+            summary.unchanged += 1;
+        } else {
+            match fs::set_permissions(path, fs::Permissions::from_mode(new)) {
+                Ok(()) => summary.changed += 1,
+                Err(e) => summary.failed.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if let Some(warning) = mode_warning(new, m.is_dir()) {
+            summary
+                .warnings
+                .push(format!("{}: {}", path.display(), warning));
+        }
+
+        if recursive && m.is_dir() {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    summary.failed.push(format!("{}: {}", path.display(), e));
+                    return Ok(());
+                }
+            };
+            for entry in entries {
+                match entry {
+                    Ok(entry) => self.change(&entry.path(), false, recursive, summary, progress)?,
+                    Err(e) => summary.failed.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Count an entry a permission or ownership change has reached.  Files count with their size, so progress lines
+/// up with the total measured before the walk.
+pub fn entry_walked(m: &fs::Metadata, progress: &mut dyn Progress) {
+    if m.is_file() {
+        progress.bytes(m.len());
+    }
+    progress.entry_done();
+}
+
+impl FromStr for ModeChange {
+    type Err = Error;
+
+    /// A single mode applies to files and directories alike.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some((files, dirs)) => Ok(Self {
+                files: files.parse()?,
+                dirs: dirs.parse()?,
+            }),
+            None => {
+                let spec: ModeSpec = s.parse()?;
+                Ok(Self {
+                    files: spec.clone(),
+                    dirs: spec,
+                })
+            }
+        }
+    }
+}
+
+/// Labels of the permission editor's rows, one per class
+pub const EDITOR_ROWS: [&str; 3] = ["user", "group", "other"];
+
+/// Labels of the permission editor's columns
+pub const EDITOR_COLUMNS: [&str; 4] = ["read", "write", "execute", "special"];
+
+/// The permission editor: a grid of mode bits with a cursor.  Rows are the classes, columns are read, write,
+/// execute and the class's special bit (setuid, setgid or sticky).
+#[derive(Debug, Default)]
+pub struct PermissionEditor {
+    pub mode: u32,
+    /// Whether the entry being edited is a directory, which changes what `mode_warning` has to say
+    pub is_dir: bool,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl PermissionEditor {
+    pub fn new(mode: u32, is_dir: bool) -> Self {
+        Self {
+            mode: mode & MODE_MASK,
+            is_dir,
+            row: 0,
+            col: 0,
+        }
+    }
+
+    /// The bit in a cell of the grid
+    fn bit(row: usize, col: usize) -> u32 {
+        let class = &CLASSES[row];
+        [class.read, class.write, class.execute, class.special][col]
+    }
+
+    pub fn is_set(&self, row: usize, col: usize) -> bool {
+        self.mode & Self::bit(row, col) != 0
+    }
+
+    /// Flip the bit under the cursor.
+    pub fn toggle(&mut self) {
+        self.mode ^= Self::bit(self.row, self.col);
+    }
+
+    pub fn up(&mut self) {
+        self.row = self.row.saturating_sub(1);
+    }
+
+    pub fn down(&mut self) {
+        self.row = (self.row + 1).min(EDITOR_ROWS.len() - 1);
+    }
+
+    pub fn left(&mut self) {
+        self.col = self.col.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.col = (self.col + 1).min(EDITOR_COLUMNS.len() - 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_roundtrip_permissions() {
+        let str = "---rwxr-x";
+        assert_eq!(
+            str,
+            permissions_to_string(string_to_permissions(str).unwrap())
+        );
+        assert_eq!(string_to_permissions("rwxrwxrwq"), None);
+    }
+
+    #[test]
+    fn test_special_bits() {
+        for (mode, string) in &[
+            (0o4755, "rwsr-xr-x"),
+            (0o4644, "rwSr--r--"),
+            (0o2775, "rwxrwsr-x"),
+            (0o1777, "rwxrwxrwt"),
+            (0o1776, "rwxrwxrwT"),
+        ] {
+            assert_eq!(permissions_to_string(*mode), *string);
+            assert_eq!(string_to_permissions(string), Some(*mode));
+        }
+        // `s` is only meaningful for user and group, `t` only for other
+        assert_eq!(string_to_permissions("rwtr-xr-x"), None);
+        assert_eq!(string_to_permissions("rwxr-xr-s"), None);
+        assert!(mode_warning(0o4777, false).is_some());
+        assert!(mode_warning(0o4755, false).is_none());
+    }
+
+    #[test]
+    fn test_mode_specs() {
+        let apply =
+            |spec: &str, current, is_dir| ModeSpec::parse(spec, 0).unwrap().apply(current, is_dir);
+        assert_eq!(apply("644", 0o777, false), 0o644);
+        assert_eq!(apply("4755", 0o644, false), 0o4755);
+        assert_eq!(apply("rw-r-----", 0o777, false), 0o640);
+        assert_eq!(apply("u+x", 0o644, false), 0o744);
+        assert_eq!(apply("go-w", 0o666, false), 0o644);
+        assert_eq!(apply("a=r", 0o755, false), 0o444);
+        assert_eq!(apply("g-w,o=", 0o777, false), 0o750);
+        assert_eq!(apply("+x", 0o600, false), 0o711);
+        assert_eq!(apply("g=u", 0o740, false), 0o770);
+        assert_eq!(apply("u=rw,go=r", 0o777, false), 0o644);
+        // X only applies to directories and files that are already executable
+        assert_eq!(apply("a+X", 0o644, false), 0o644);
+        assert_eq!(apply("a+X", 0o744, false), 0o755);
+        assert_eq!(apply("a+X", 0o700, true), 0o711);
+        assert_eq!(apply("u+s", 0o755, false), 0o4755);
+        assert_eq!(apply("g+s,o+t", 0o775, true), 0o3775);
+        assert_eq!(apply("+t", 0o777, true), 0o1777);
+        assert_eq!(apply("u-s", 0o6755, false), 0o2755);
+        // `=` resets the special bit of the classes it names
+        assert_eq!(apply("u=rwx", 0o4755, false), 0o755);
+        assert_eq!(apply("go=rx", 0o4755, false), 0o4755);
+        assert_eq!(apply("rwsr-xr-x", 0o644, false), 0o4755);
+
+        // Without a class, bits in the umask are left alone
+        let apply_masked =
+            |spec: &str, current| ModeSpec::parse(spec, 0o022).unwrap().apply(current, false);
+        assert_eq!(apply_masked("+w", 0o444), 0o644);
+        assert_eq!(apply_masked("-w", 0o666), 0o466);
+        assert_eq!(apply_masked("=r", 0o777), 0o466);
+        assert_eq!(apply_masked("a+w", 0o444), 0o666);
+
+        for bad in &["", "8", "17777", "u+q", "z+x", "u", "rwxrwx"] {
+            assert!(bad.parse::<ModeSpec>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn test_permission_editor() {
+        let mut editor = PermissionEditor::new(0o100644, false);
+        assert_eq!(editor.mode, 0o644);
+        // Group write, then the special bit of other: sticky
+        editor.down();
+        editor.right();
+        editor.toggle();
+        editor.down();
+        editor.down();
+        editor.right();
+        editor.right();
+        editor.right();
+        editor.toggle();
+        assert_eq!((editor.row, editor.col), (2, 3));
+        assert_eq!(editor.mode, 0o1664);
+        assert!(editor.is_set(2, 3));
+    }
+
+    #[test]
+    fn test_recursive_change() {
+        use crate::fs_ops::NoProgress;
+
+        let dir = std::env::temp_dir().join(format!("file_utility_chmod_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("plain"), "").unwrap();
+        fs::write(dir.join("sub/script"), "").unwrap();
+        fs::set_permissions(dir.join("sub/script"), fs::Permissions::from_mode(0o700)).unwrap();
+        let mode =
+            |path: &str| fs::metadata(dir.join(path)).unwrap().permissions().mode() & MODE_MASK;
+
+        let mut summary = ChangeSummary::default();
+        let change: ModeChange = "644:750".parse().unwrap();
+        change
+            .apply(&dir, false, &mut summary, &mut NoProgress)
+            .unwrap();
+        assert_eq!((mode(""), mode("plain")), (0o750, 0o644));
+
+        // X keeps execute on the script and the directories, but doesn't add it to the plain file
+        let mut summary = ChangeSummary::default();
+        let change: ModeChange = "a=rX,u+w".parse().unwrap();
+        change
+            .apply(&dir, true, &mut summary, &mut NoProgress)
+            .unwrap();
+        assert_eq!(
+            (mode(""), mode("sub"), mode("plain"), mode("sub/script")),
+            (0o755, 0o755, 0o644, 0o755)
+        );
+        assert!(summary.failed.is_empty());
+        assert_eq!(summary.changed + summary.unchanged, 4);
+
+        // A link met while recursing is left alone, and so is what it points to
+        let outside = dir.with_extension("outside");
+        fs::write(&outside, "").unwrap();
+        fs::set_permissions(&outside, fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("sub/link")).unwrap();
+        let mut summary = ChangeSummary::default();
+        let change: ModeChange = "go+r".parse().unwrap();
+        change
+            .apply(&dir, true, &mut summary, &mut NoProgress)
+            .unwrap();
+        let outside_mode = || fs::metadata(&outside).unwrap().permissions().mode() & MODE_MASK;
+        assert_eq!(outside_mode(), 0o600);
+        assert_eq!(summary.changed + summary.unchanged, 4);
+
+        fs::remove_file(&outside).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_change_through_link() {
+        use crate::fs_ops::NoProgress;
+
+        let dir =
+            std::env::temp_dir().join(format!("file_utility_chmod_link_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("target"), "").unwrap();
+        fs::set_permissions(dir.join("target"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("target", dir.join("link")).unwrap();
+
+        // A link given as the target changes what it points to, as chmod does
+        let mut summary = ChangeSummary::default();
+        let change: ModeChange = "go+r".parse().unwrap();
+        change
+            .apply(&dir.join("link"), false, &mut summary, &mut NoProgress)
+            .unwrap();
+        let mode = fs::metadata(dir.join("target"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & MODE_MASK;
+        assert_eq!(mode, 0o644);
+        assert_eq!((summary.changed, summary.failed.len()), (1, 0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
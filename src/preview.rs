@@ -0,0 +1,490 @@
+//! `preview` reads the contents shown in the details pane.
+//!
+//! Files are read one bounded page at a time on a worker thread, and pages are cached by path, modification time and
+//! offset, so drawing a frame never touches more than a stat of the selected file.
+
+// Events are keyed on termion keys
+use termion::event::Key;
+
+// Styled text for the details pane
+use tui::text::Spans;
+
+use crate::{events::Event, highlight::Highlighter};
+
+// Input and output, threads and caching
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread,
+    time::SystemTime,
+};
+
+/// Bytes read per page
+pub const PAGE_SIZE: u64 = 16 * 1024;
+
+/// Bytes per line of the hex view
+pub const HEX_WIDTH: usize = 16;
+
+/// Pages kept in the cache before the oldest are dropped
+const CACHE_PAGES: usize = 64;
+
+/// Identifies one page of one version of a file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PageKey {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+    pub offset: u64,
+}
+
+/// A window of a file's contents
+#[derive(Debug)]
+pub struct Page {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+    /// Whether the bytes look like something other than text
+    pub is_binary: bool,
+    /// Number of the file line the page starts in, counting from 1, once it is known
+    pub first_line: Option<u64>,
+    /// The page as styled text, unless it is binary
+    pub lines: Vec<Spans<'static>>,
+}
+
+/// How file contents are shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewMode {
+    /// Hex for binary pages, text otherwise
+    Auto,
+    Hex,
+    Text,
+}
+
+impl ViewMode {
+    pub fn next(self) -> Self {
+        match self {
+            ViewMode::Auto => ViewMode::Hex,
+            ViewMode::Hex => ViewMode::Text,
+            ViewMode::Text => ViewMode::Auto,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ViewMode::Auto => "auto",
+            ViewMode::Hex => "hex",
+            ViewMode::Text => "text",
+        }
+    }
+
+    /// Whether a page is shown as a hex dump
+    pub fn shows_hex(self, page: &Page) -> bool {
+        match self {
+            ViewMode::Auto => page.is_binary,
+            ViewMode::Hex => true,
+            ViewMode::Text => false,
+        }
+    }
+}
+
+/// Guess whether bytes are binary data: they contain a NUL, or aren't UTF-8.
+/// A multi-byte character cut off by the end of the window doesn't count.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return true;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => false,
+        // `error_len() == None` means the input just ended part way through a character
+        Err(e) => e.error_len().is_some() || bytes.len() - e.valid_up_to() > 3,
+    }
+}
+
+/// Render bytes like `xxd`: offset, sixteen bytes in pairs, then the printable ASCII.
+pub fn hex_lines(offset: u64, bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(HEX_WIDTH)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let mut line = format!("{:08x}: ", offset + (idx * HEX_WIDTH) as u64);
+            for pos in 0..HEX_WIDTH {
+                match chunk.get(pos) {
+                    Some(b) => line.push_str(&format!("{:02x}", b)),
+                    None => line.push_str("  "),
+                }
+                if pos % 2 == 1 {
+                    line.push(' ');
+                }
+            }
+            line.push(' ');
+            line.extend(chunk.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            line
+        })
+        .collect()
+}
+
+/// Parse an offset typed by the user: decimal, or hexadecimal with a `0x` prefix.
+pub fn parse_offset(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Sent from the worker when a page has been read
+#[derive(Debug)]
+pub struct PreviewEvent {
+    key: PageKey,
+    page: Result<Page, String>,
+}
+
+/// What the details pane can show for the selected entry right now
+pub enum PreviewState<'a> {
+    /// There is nothing to preview, for the given reason
+    Unavailable(&'static str),
+    Loading,
+    Ready(&'a Page),
+    Failed(&'a str),
+}
+
+/// The first line of `bytes`, as far as it goes
+fn first_line_of(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn count_newlines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|&&b| b == b'\n').count() as u64
+}
+
+impl Page {
+    /// Number of the line the following page starts in, if this page's is known
+    fn next_first_line(&self) -> Option<u64> {
+        self.first_line
+            .map(|first_line| first_line + count_newlines(&self.bytes))
+    }
+}
+
+/// Read one page of a file, styling it if it is text.  Line numbers are only known for the first page; the others
+/// are counted from their neighbours once those have been read, rather than by reading everything before them.
+fn read_page(key: &PageKey, highlighter: &Highlighter) -> Result<Page, String> {
+    let read = || -> io::Result<Page> {
+        let mut f = File::open(&key.path)?;
+        f.seek(SeekFrom::Start(key.offset))?;
+        let mut bytes = Vec::with_capacity(PAGE_SIZE as usize);
+        f.by_ref().take(PAGE_SIZE).read_to_end(&mut bytes)?;
+        let is_binary = looks_binary(&bytes);
+        let lines = if is_binary {
+            Vec::new()
+        } else {
+            // The file's first line can name its syntax, as with `#!/bin/sh`
+            let first_line = if key.offset == 0 {
+                first_line_of(&bytes)
+            } else {
+                let mut head = Vec::new();
+                f.seek(SeekFrom::Start(0))?;
+                f.take(PAGE_SIZE).read_to_end(&mut head)?;
+                first_line_of(&head)
+            };
+            highlighter.lines(&key.path, &first_line, &String::from_utf8_lossy(&bytes))
+        };
+        Ok(Page {
+            offset: key.offset,
+            bytes,
+            is_binary,
+            first_line: (key.offset == 0).then_some(1),
+            lines,
+        })
+    };
+    read().map_err(|e| e.to_string())
+}
+
+/// Reads pages in the background, caches them, and tracks where the user has scrolled to.
+pub struct Previewer {
+    requests: Sender<PageKey>,
+    cache: HashMap<PageKey, Result<Page, String>>,
+    /// Cache keys, oldest first
+    order: VecDeque<PageKey>,
+    pending: HashSet<PageKey>,
+    /// The file being previewed; scrolling resets when it changes
+    path: Option<PathBuf>,
+    /// Start of the current page
+    pub offset: u64,
+    /// Lines scrolled down within the current page
+    pub scroll: u16,
+    /// Size of the file being previewed
+    pub len: u64,
+    pub view: ViewMode,
+    pub line_numbers: bool,
+    /// Wrap long lines instead of cutting them off
+    pub wrap: bool,
+    /// A line to scroll to, by the offset it starts at, once its page has been read
+    line_target: Option<u64>,
+}
+
+impl Previewer {
+    /// Finished pages are delivered through `events`.
+    pub fn new(events: Sender<Event<Key>>) -> Self {
+        let (requests, rx) = mpsc::channel::<PageKey>();
+        thread::spawn(move || {
+            let highlighter = Highlighter::new();
+            for key in rx {
+                let page = read_page(&key, &highlighter);
+                if events
+                    .send(Event::Preview(PreviewEvent { key, page }))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        Self {
+            requests,
+            cache: HashMap::new(),
+            order: VecDeque::new(),
+            pending: HashSet::new(),
+            path: None,
+            offset: 0,
+            scroll: 0,
+            len: 0,
+            view: ViewMode::Auto,
+            line_numbers: false,
+            wrap: false,
+            line_target: None,
+        }
+    }
+
+    /// Store a page the worker has read.
+    pub fn insert(&mut self, event: PreviewEvent) {
+        self.pending.remove(&event.key);
+        if self.cache.contains_key(&event.key) {
+            // A page read again moves to the back instead of being listed twice
+            self.order.retain(|key| *key != event.key);
+        } else if self.cache.len() >= CACHE_PAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.cache.remove(&oldest);
+            }
+        }
+        self.order.push_back(event.key.clone());
+        self.cache.insert(event.key, event.page);
+    }
+
+    /// The preview of `path` at the current scroll position, asking the worker for it if it isn't cached yet.
+    pub fn show(&mut self, path: Option<&Path>) -> PreviewState<'_> {
+        if self.path.as_deref() != path {
+            self.path = path.map(Path::to_path_buf);
+            self.offset = 0;
+            self.scroll = 0;
+            self.view = ViewMode::Auto;
+            self.line_target = None;
+        }
+        let path = match path {
+            Some(path) => path,
+            None => return PreviewState::Unavailable("Nothing selected."),
+        };
+        // Only regular files have contents that are safe to read: think of FIFOs or /dev/zero
+        let m = match fs::metadata(path) {
+            Ok(m) if m.is_file() => m,
+            Ok(_) => return PreviewState::Unavailable("Not a regular file."),
+            Err(_) => return PreviewState::Unavailable("Could not read metadata."),
+        };
+        self.len = m.len();
+        let key = PageKey {
+            path: path.to_path_buf(),
+            modified: m.modified().ok(),
+            offset: self.offset,
+        };
+        if !self.cache.contains_key(&key) {
+            if self.pending.insert(key.clone()) {
+                let _ = self.requests.send(key);
+            }
+            return PreviewState::Loading;
+        }
+        self.count_lines(&key);
+        if let (Some(target), Ok(page)) = (self.line_target.take(), &self.cache[&key]) {
+            let before = (target.saturating_sub(page.offset) as usize).min(page.bytes.len());
+            self.scroll = count_newlines(&page.bytes[..before]) as u16;
+        }
+        match &self.cache[&key] {
+            Ok(page) => PreviewState::Ready(page),
+            Err(e) => PreviewState::Failed(e),
+        }
+    }
+
+    /// Work out the line the page at `key` starts in from the page before or after it, if either is cached.
+    fn count_lines(&mut self, key: &PageKey) {
+        let neighbour = |offset| match self.cache.get(&PageKey {
+            offset,
+            ..key.clone()
+        }) {
+            Some(Ok(page)) => Some(page),
+            _ => None,
+        };
+        let first_line = match &self.cache[key] {
+            Ok(page) if page.first_line.is_none() => {
+                let before = key.offset.checked_sub(PAGE_SIZE).and_then(neighbour);
+                let after = neighbour(key.offset + PAGE_SIZE);
+                before.and_then(Page::next_first_line).or_else(|| {
+                    let next = after?.first_line?;
+                    Some(next.saturating_sub(count_newlines(&page.bytes)))
+                })
+            }
+            _ => return,
+        };
+        if let Some(Ok(page)) = self.cache.get_mut(key) {
+            page.first_line = first_line;
+        }
+    }
+
+    pub fn page_down(&mut self) {
+        if self.offset + PAGE_SIZE < self.len {
+            self.offset += PAGE_SIZE;
+            self.scroll = 0;
+        }
+    }
+
+    pub fn page_up(&mut self) {
+        if self.offset > 0 {
+            self.offset = self.offset.saturating_sub(PAGE_SIZE);
+            self.scroll = 0;
+        }
+    }
+
+    pub fn toggle_line_numbers(&mut self) {
+        self.line_numbers = !self.line_numbers;
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+    }
+
+    pub fn cycle_view(&mut self) {
+        self.view = self.view.next();
+        self.scroll = 0;
+    }
+
+    /// Switch to the hex view with the line holding `offset` at the top.
+    pub fn jump_to(&mut self, offset: u64) {
+        let offset = offset.min(self.len.saturating_sub(1));
+        self.offset = offset - offset % PAGE_SIZE;
+        self.scroll = ((offset % PAGE_SIZE) / HEX_WIDTH as u64) as u16;
+        self.view = ViewMode::Hex;
+    }
+
+    /// Show `path` as text with the line starting at byte `line_start` at the top.  Wrapping is turned off, as the
+    /// preview scrolls by screen rows and wrapped lines above would leave the line further down.
+    pub fn jump_to_line(&mut self, path: &Path, line_start: u64) {
+        self.path = Some(path.to_path_buf());
+        self.offset = line_start - line_start % PAGE_SIZE;
+        self.scroll = 0;
+        self.view = ViewMode::Auto;
+        self.wrap = false;
+        self.line_target = Some(line_start);
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_add(1);
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_read_page_is_bounded() {
+        let path = std::env::temp_dir().join(format!("file_utility_page_{}", std::process::id()));
+        let contents: Vec<u8> = (0..PAGE_SIZE * 2 + 10).map(|i| i as u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        let highlighter = Highlighter::new();
+        let key = |offset| PageKey {
+            path: path.clone(),
+            modified: None,
+            offset,
+        };
+        let second = read_page(&key(PAGE_SIZE), &highlighter).unwrap();
+        assert_eq!(
+            second.bytes,
+            &contents[PAGE_SIZE as usize..2 * PAGE_SIZE as usize]
+        );
+        assert!(second.is_binary);
+        // Only the first page knows its line number without the pages before it
+        assert_eq!(second.first_line, None);
+        let first = read_page(&key(0), &highlighter).unwrap();
+        assert_eq!(first.first_line, Some(1));
+        // Every 256 bytes of the counting pattern holds one newline
+        assert_eq!(first.next_first_line(), Some(PAGE_SIZE / 256 + 1));
+        let last = read_page(&key(2 * PAGE_SIZE), &highlighter).unwrap();
+        assert_eq!(last.bytes.len(), 10);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let (tx, _rx) = mpsc::channel();
+        let mut previewer = Previewer::new(tx);
+        let event = |offset| PreviewEvent {
+            key: PageKey {
+                path: PathBuf::from("/file"),
+                modified: None,
+                offset,
+            },
+            page: Err(String::new()),
+        };
+        for offset in 0..CACHE_PAGES as u64 {
+            previewer.insert(event(offset));
+        }
+        // Reading the same page again neither lists it twice nor drops another page
+        for _ in 0..3 {
+            previewer.insert(event(0));
+        }
+        assert_eq!(previewer.cache.len(), CACHE_PAGES);
+        assert_eq!(previewer.order.len(), CACHE_PAGES);
+        // The page read last is now the newest, so the next one evicts page 1 instead
+        previewer.insert(event(CACHE_PAGES as u64));
+        assert!(previewer.cache.contains_key(&event(0).key));
+        assert!(!previewer.cache.contains_key(&event(1).key));
+        assert_eq!(previewer.order.len(), CACHE_PAGES);
+    }
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary("plain text, ünïcode".as_bytes()));
+        // A character cut in half by the end of the page is still text
+        assert!(!looks_binary(&"ü".as_bytes()[..1]));
+        assert!(looks_binary(b"ELF\0\x01"));
+        assert!(looks_binary(b"\xff\xfe not utf-8"));
+    }
+
+    #[test]
+    fn test_hex_lines() {
+        assert_eq!(
+            hex_lines(0x20, b"Hello, world!\n\x00\x01\xff"),
+            vec![
+                "00000020: 4865 6c6c 6f2c 2077 6f72 6c64 210a 0001  Hello, world!...",
+                "00000030: ff                                       .",
+            ]
+        );
+        assert_eq!(parse_offset("0x1F"), Some(31));
+        assert_eq!(parse_offset(" 42 "), Some(42));
+        assert_eq!(parse_offset("nope"), None);
+    }
+}
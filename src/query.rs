@@ -0,0 +1,511 @@
+//! `query` finds files by their metadata, a little like `find`.
+//!
+//! A query is a list of tests joined with `and`, `or` and `not`, with parentheses for grouping.  Tests next to each
+//! other without a word in between must both pass, so `type:f size>1M` works.  The tests are:
+//!
+//! * `size>100M`, `size<=4k`, `size=0`: size in bytes, with k, M, G or T for powers of 1024
+//! * `mtime<7d`, `atime>1h`, `ctime>=30m`: time since the entry was modified, accessed or changed, in s, m, h, d or w
+//! * `perm:o+w`, `perm:u-x`, `perm:644`: `+` bits must be set, `-` bits clear; a full mode must match exactly
+//! * `type:f`: one of f, d, l, p, s, b or c, as with `find -type`
+//! * `user:root`, `group:100`: owner and group, by name or ID
+//! * `name:*.rs`: a glob matched against the file name
+//!
+//! Walking happens on a worker thread that reports matches back through the `Events` channel.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{anyhow, bail, Context, Result};
+// Events are keyed on termion keys
+use termion::event::Key;
+
+use globset::{Glob, GlobMatcher};
+use ignore::WalkBuilder;
+
+use crate::{
+    app::FileListing,
+    events::Event,
+    permissions::{ModeSpec, MODE_MASK},
+    users::Users,
+};
+
+// Metadata to test, and threads to walk the tree on
+use std::{
+    fs::Metadata,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Minimum time between two batches from the walker
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How a number in a test compares
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Cmp {
+    /// Split `<=7d` and the like into the comparison and what follows it
+    fn parse(s: &str) -> Option<(Self, &str)> {
+        [
+            ("<=", Cmp::LessOrEqual),
+            (">=", Cmp::GreaterOrEqual),
+            ("<", Cmp::Less),
+            (">", Cmp::Greater),
+            ("=", Cmp::Equal),
+        ]
+        .iter()
+        .find_map(|&(prefix, cmp)| Some((cmp, s.strip_prefix(prefix)?)))
+    }
+
+    fn holds(self, value: u64, limit: u64) -> bool {
+        match self {
+            Cmp::Less => value < limit,
+            Cmp::LessOrEqual => value <= limit,
+            Cmp::Equal => value == limit,
+            Cmp::GreaterOrEqual => value >= limit,
+            Cmp::Greater => value > limit,
+        }
+    }
+}
+
+/// Which of an entry's times a test looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeField {
+    Modified,
+    Accessed,
+    Changed,
+}
+
+/// A single test on an entry
+#[derive(Debug)]
+enum Test {
+    Size(Cmp, u64),
+    /// Seconds since the given time
+    Age(TimeField, Cmp, u64),
+    /// Bits that must be set, and bits that must be clear
+    Perm {
+        set: u32,
+        clear: u32,
+    },
+    /// As `find -type` spells it
+    Type(char),
+    User(u32),
+    Group(u32),
+    Name(GlobMatcher),
+}
+
+/// A number with a unit suffix, like `100M` or `7d`
+fn parse_amount(s: &str, units: &[(char, u64)]) -> Result<u64> {
+    let (number, scale) = match s.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            let scale = units
+                .iter()
+                .find(|&&(unit, _)| unit == c)
+                .map(|&(_, scale)| scale)
+                .ok_or_else(|| anyhow!("Unknown unit {:?} in {:?}", c, s))?;
+            (&s[..s.len() - 1], scale)
+        }
+        _ => (s, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Expected a number, not {:?}", s))?;
+    number
+        .checked_mul(scale)
+        .ok_or_else(|| anyhow!("{:?} is too big", s))
+}
+
+const SIZE_UNITS: &[(char, u64)] = &[
+    ('k', 1 << 10),
+    ('K', 1 << 10),
+    ('M', 1 << 20),
+    ('G', 1 << 30),
+    ('T', 1 << 40),
+];
+
+const TIME_UNITS: &[(char, u64)] = &[
+    ('s', 1),
+    ('m', 60),
+    ('h', 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('w', 7 * 24 * 60 * 60),
+];
+
+impl Test {
+    fn parse(word: &str, users: &Users) -> Result<Self> {
+        if let Some((field, value)) = word.split_once(':') {
+            return Ok(match field {
+                "perm" => {
+                    let spec: ModeSpec = value.parse()?;
+                    match spec {
+                        ModeSpec::Absolute(mode) => Test::Perm {
+                            set: mode,
+                            clear: MODE_MASK & !mode,
+                        },
+                        // What adding to nothing turns on must be on, what taking from everything turns off must be off
+                        ModeSpec::Symbolic(_) => Test::Perm {
+                            set: spec.apply(0, false),
+                            clear: MODE_MASK & !spec.apply(MODE_MASK, false),
+                        },
+                    }
+                }
+                "type" => match value {
+                    "f" | "d" | "l" | "p" | "s" | "b" | "c" => {
+                        Test::Type(value.chars().next().unwrap())
+                    }
+                    _ => bail!("Unknown type {:?}: use f, d, l, p, s, b or c", value),
+                },
+                "user" => Test::User(users.uid(value)?),
+                "group" => Test::Group(users.gid(value)?),
+                "name" => Test::Name(Glob::new(value)?.compile_matcher()),
+                _ => bail!("Unknown test {:?}", word),
+            });
+        }
+        let split = word
+            .find(['<', '>', '='])
+            .ok_or_else(|| anyhow!("Unknown test {:?}", word))?;
+        let (field, rest) = word.split_at(split);
+        let (cmp, value) =
+            Cmp::parse(rest).ok_or_else(|| anyhow!("Unknown comparison in {:?}", word))?;
+        let time =
+            |field| -> Result<Test> { Ok(Test::Age(field, cmp, parse_amount(value, TIME_UNITS)?)) };
+        match field {
+            "size" => Ok(Test::Size(cmp, parse_amount(value, SIZE_UNITS)?)),
+            "mtime" => time(TimeField::Modified),
+            "atime" => time(TimeField::Accessed),
+            "ctime" => time(TimeField::Changed),
+            _ => bail!("Unknown test {:?}", word),
+        }
+    }
+
+    fn matches(&self, name: &str, m: &Metadata, now: SystemTime) -> bool {
+        match self {
+            Test::Size(cmp, limit) => cmp.holds(m.len(), *limit),
+            Test::Age(field, cmp, limit) => {
+                let time = match field {
+                    TimeField::Modified => m.modified().ok(),
+                    TimeField::Accessed => m.accessed().ok(),
+                    TimeField::Changed => SystemTime::UNIX_EPOCH
+                        .checked_add(Duration::from_secs(m.ctime().max(0) as u64)),
+                };
+                // Times in the future count as just now
+                let age = time.map(|time| now.duration_since(time).unwrap_or_default().as_secs());
+                age.is_some_and(|age| cmp.holds(age, *limit))
+            }
+            Test::Perm { set, clear } => m.mode() & set == *set && m.mode() & clear == 0,
+            Test::Type(kind) => {
+                let t = m.file_type();
+                match kind {
+                    'f' => t.is_file(),
+                    'd' => t.is_dir(),
+                    'l' => t.is_symlink(),
+                    'p' => t.is_fifo(),
+                    's' => t.is_socket(),
+                    'b' => t.is_block_device(),
+                    _ => t.is_char_device(),
+                }
+            }
+            Test::User(uid) => m.uid() == *uid,
+            Test::Group(gid) => m.gid() == *gid,
+            Test::Name(glob) => glob.is_match(name),
+        }
+    }
+}
+
+/// Tests combined with `and`, `or` and `not`
+#[derive(Debug)]
+enum Expr {
+    Test(Test),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, name: &str, m: &Metadata, now: SystemTime) -> bool {
+        match self {
+            Expr::Test(test) => test.matches(name, m, now),
+            Expr::Not(expr) => !expr.matches(name, m, now),
+            Expr::And(a, b) => a.matches(name, m, now) && b.matches(name, m, now),
+            Expr::Or(a, b) => a.matches(name, m, now) || b.matches(name, m, now),
+        }
+    }
+}
+
+/// A parsed query
+#[derive(Debug)]
+pub struct Query(Expr);
+
+/// Split a query into words and parentheses.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    for word in s.split_whitespace() {
+        let mut rest = word;
+        while !rest.is_empty() {
+            let end = match rest.find(['(', ')']) {
+                Some(0) => 1,
+                Some(idx) => idx,
+                None => rest.len(),
+            };
+            tokens.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// A recursive-descent parser over the tokens.  `or` binds loosest, then `and`, then `not`.
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    users: &'a Users,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        loop {
+            match self.peek() {
+                Some("and") => self.pos += 1,
+                // Another test straight after means `and` too
+                Some(token) if token != "or" && token != ")" => {}
+                _ => return Ok(expr),
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        let token = self
+            .peek()
+            .ok_or_else(|| anyhow!("The query ends too soon"))?;
+        self.pos += 1;
+        match token {
+            "not" => Ok(Expr::Not(Box::new(self.not()?))),
+            "(" => {
+                let expr = self.or()?;
+                if self.peek() != Some(")") {
+                    bail!("Missing \")\"");
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            "and" | "or" | ")" => bail!("Expected a test, not {:?}", token),
+            _ => Ok(Expr::Test(Test::parse(token, self.users)?)),
+        }
+    }
+}
+
+impl Query {
+    /// Parse a query, looking up user and group names in `users`.
+    pub fn parse(s: &str, users: &Users) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            pos: 0,
+            users,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {:?}", token);
+        }
+        Ok(Self(expr))
+    }
+
+    /// Whether an entry called `name`, with metadata `m` from `symlink_metadata`, matches at time `now`.
+    pub fn matches(&self, name: &str, m: &Metadata, now: SystemTime) -> bool {
+        self.0.matches(name, m, now)
+    }
+}
+
+/// Sent from the walker with the next matching entries
+#[derive(Debug)]
+pub struct QueryEvent {
+    run: usize,
+    matches: Vec<FileListing>,
+    done: bool,
+}
+
+/// Walk everything below `root`, hidden and ignored files included, and send back what matches.
+fn walk(
+    root: PathBuf,
+    query: Query,
+    run: usize,
+    events: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+) {
+    let walker = WalkBuilder::new(&root)
+        .standard_filters(false)
+        .same_file_system(true)
+        .build();
+    let send = |matches, done| {
+        events
+            .send(Event::Query(QueryEvent { run, matches, done }))
+            .is_ok()
+    };
+    let now = SystemTime::now();
+    let mut matches = Vec::new();
+    let mut last_report = Instant::now();
+    for entry in walker {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let entry = match entry {
+            Ok(entry) if entry.depth() > 0 => entry,
+            _ => continue,
+        };
+        let found = entry
+            .metadata()
+            .is_ok_and(|m| query.matches(&entry.file_name().to_string_lossy(), &m, now));
+        if found {
+            matches.push(FileListing::read(entry.into_path()));
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            if !send(std::mem::take(&mut matches), false) {
+                return;
+            }
+            last_report = Instant::now();
+        }
+    }
+    send(matches, true);
+}
+
+/// A query being run, and everything it has matched so far.  While one is open, its matches take the place of
+/// the directory listing.
+pub struct QueryResults {
+    cancel: Arc<AtomicBool>,
+    run: usize,
+    pub text: String,
+    pub root: PathBuf,
+    /// Every match, including any the listing's filter hides
+    pub matches: Vec<PathBuf>,
+    /// Still walking the tree
+    pub running: bool,
+}
+
+impl QueryResults {
+    /// Start matching everything below `root` against `query`.  `run` tells this run's batches from older ones.
+    pub fn start(
+        root: &Path,
+        text: &str,
+        query: Query,
+        run: usize,
+        events: Sender<Event<Key>>,
+    ) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (walk_root, walk_cancel) = (root.to_path_buf(), cancel.clone());
+        thread::spawn(move || walk(walk_root, query, run, events, walk_cancel));
+        Self {
+            cancel,
+            run,
+            text: text.to_string(),
+            root: root.to_path_buf(),
+            matches: Vec::new(),
+            running: true,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.running = false;
+    }
+
+    /// Take a batch from the walker, returning the new matches, or nothing if the batch is from another run.
+    pub fn insert(&mut self, event: QueryEvent) -> Vec<FileListing> {
+        if event.run != self.run {
+            return Vec::new();
+        }
+        if event.done {
+            self.running = false;
+        }
+        self.matches.extend(
+            event
+                .matches
+                .iter()
+                .map(|listing| listing.path().to_path_buf()),
+        );
+        event.matches
+    }
+}
+
+impl Drop for QueryResults {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_errors() {
+        let users = Users::default();
+        for bad in &[
+            "",
+            "size>",
+            "size>10Q",
+            "sighs>10",
+            "type:x",
+            "perm:o+q",
+            "user:nobody-here",
+            "(type:f",
+            "type:f)",
+            "type:f or",
+            "and type:f",
+        ] {
+            assert!(Query::parse(bad, &users).is_err(), "{:?} parsed", bad);
+        }
+        assert!(Query::parse("not (type:f or type:d) size=0 and user:0", &users).is_ok());
+    }
+
+    #[test]
+    fn test_matches() {
+        let dir = std::env::temp_dir().join(format!("file_utility_query_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("big.rs");
+        fs::write(&path, vec![b'x'; 3000]).unwrap();
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o646)).unwrap();
+        let m = fs::symlink_metadata(&path).unwrap();
+        let users = Users::default();
+        let now = SystemTime::now();
+        let matches = |s| Query::parse(s, &users).unwrap().matches("big.rs", &m, now);
+
+        assert!(matches("size>2k and size<3K"));
+        assert!(!matches("size>2k size<2K"));
+        assert!(matches("mtime<1h type:f"));
+        assert!(!matches("mtime>1d or type:d"));
+        assert!(matches("perm:o+w perm:u-x"));
+        assert!(!matches("perm:g+w"));
+        assert!(matches("perm:646 and not perm:644"));
+        assert!(matches("name:*.rs (type:l or user:0 or size>1k)"));
+        // Globs match case
+        assert!(matches("not name:*.RS"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+//! `search` looks through the contents of every file below a directory, like `grep -r`.
+//!
+//! A worker thread walks the tree the same way the fuzzy finder does, skips files that look binary, and streams
+//! matching lines back through the `Events` channel while the results list fills in.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{bail, Result};
+// Events are keyed on termion keys
+use termion::event::Key;
+
+use ignore::WalkBuilder;
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::{events::Event, preview::looks_binary, stateful_list::StatefulList};
+
+// Reading files on a worker thread
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two batches from the worker
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The search stops after this many matching lines
+const MAX_HITS: usize = 10_000;
+
+/// Snippets are cut down to this many characters
+const SNIPPET_CHARS: usize = 200;
+
+/// Only this much of a line is searched.  The rest of a longer one, as in minified files, is skipped over rather than
+/// read into memory.
+const MAX_LINE_BYTES: u64 = 64 * 1024;
+
+/// Whether letter case matters
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CaseMode {
+    /// Case matters only when the pattern has an uppercase letter
+    #[default]
+    Smart,
+    Insensitive,
+    Sensitive,
+}
+
+impl CaseMode {
+    pub fn next(self) -> Self {
+        match self {
+            CaseMode::Smart => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Sensitive,
+            CaseMode::Sensitive => CaseMode::Smart,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CaseMode::Smart => "smart case",
+            CaseMode::Insensitive => "ignoring case",
+            CaseMode::Sensitive => "matching case",
+        }
+    }
+}
+
+/// How the pattern is read
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SearchOptions {
+    /// A regular expression rather than a fixed string
+    pub regex: bool,
+    pub case: CaseMode,
+}
+
+impl SearchOptions {
+    /// Compile `pattern` for searching file contents.
+    pub fn compile(&self, pattern: &str) -> Result<Regex> {
+        if pattern.is_empty() {
+            bail!("Enter something to search for");
+        }
+        let ignore_case = match self.case {
+            CaseMode::Smart => !pattern.chars().any(char::is_uppercase),
+            CaseMode::Insensitive => true,
+            CaseMode::Sensitive => false,
+        };
+        let pattern = if self.regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        Ok(RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()?)
+    }
+
+    pub fn label(&self) -> String {
+        format!(
+            "{}, {}",
+            if self.regex { "regex" } else { "fixed string" },
+            self.case.label()
+        )
+    }
+}
+
+/// One matching line
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub path: PathBuf,
+    /// Line number, from 1
+    pub line: u64,
+    /// Where the line starts in the file
+    pub offset: u64,
+    /// The line, trimmed and shortened
+    pub snippet: String,
+    /// The first match within `snippet`
+    pub matched: Range<usize>,
+}
+
+/// Cut a matching line down to a snippet, keeping the match in view, and find the match in it.
+fn snippet(line: &[u8], matched: Range<usize>) -> (String, Range<usize>) {
+    let line = String::from_utf8_lossy(line);
+    // Replacing invalid UTF-8 can shift the match; then it just isn't highlighted
+    let (start, end) = match line.get(matched.clone()) {
+        Some(_) => (matched.start, matched.end),
+        None => (0, 0),
+    };
+    let trimmed_start = line.len() - line.trim_start().len();
+    let trimmed = line.trim();
+    let start = start.saturating_sub(trimmed_start).min(trimmed.len());
+    let end = end
+        .saturating_sub(trimmed_start)
+        .min(trimmed.len())
+        .max(start);
+    // Leave some context before the match, but get it into the snippet
+    let mut from = 0;
+    let chars_before = trimmed[..start].chars().count();
+    if chars_before > SNIPPET_CHARS / 2 {
+        from = trimmed
+            .char_indices()
+            .nth(chars_before - SNIPPET_CHARS / 4)
+            .map_or(0, |(idx, _)| idx);
+    }
+    let to = trimmed[from..]
+        .char_indices()
+        .nth(SNIPPET_CHARS)
+        .map_or(trimmed.len(), |(idx, _)| from + idx);
+    (
+        trimmed[from..to].to_string(),
+        (start - from).min(to - from)..(end - from).min(to - from),
+    )
+}
+
+/// Search one file, reporting each matching line.  Files that look binary are skipped.
+fn search_file(
+    path: &Path,
+    regex: &Regex,
+    cancel: &AtomicBool,
+    mut report: impl FnMut(SearchHit),
+) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if looks_binary(reader.fill_buf()?) {
+        return Ok(());
+    }
+    let mut buf = Vec::new();
+    let (mut line, mut offset) = (0, 0);
+    loop {
+        buf.clear();
+        let mut n = reader
+            .by_ref()
+            .take(MAX_LINE_BYTES)
+            .read_until(b'\n', &mut buf)?;
+        if n == 0 || cancel.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if n as u64 == MAX_LINE_BYTES && !buf.ends_with(b"\n") {
+            n += reader.skip_until(b'\n')?;
+        }
+        line += 1;
+        let content = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content.contains(&0) {
+            // Binary data further in
+            return Ok(());
+        }
+        if let Some(m) = regex.find(content) {
+            let (snippet, matched) = snippet(content, m.range());
+            report(SearchHit {
+                path: path.to_path_buf(),
+                line,
+                offset,
+                snippet,
+                matched,
+            });
+        }
+        offset += n as u64;
+    }
+}
+
+/// Sent from the worker with the next matching lines
+#[derive(Debug)]
+pub struct SearchEvent {
+    search: usize,
+    hits: Vec<SearchHit>,
+    files: usize,
+    done: bool,
+}
+
+/// Search every file below `root` until done or `cancel` is set.
+fn search_tree(
+    root: PathBuf,
+    regex: Regex,
+    search: usize,
+    events: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+) {
+    let walker = WalkBuilder::new(&root)
+        .require_git(false)
+        .same_file_system(true)
+        .build();
+    let send = |hits, files, done| {
+        events
+            .send(Event::Search(SearchEvent {
+                search,
+                hits,
+                files,
+                done,
+            }))
+            .is_ok()
+    };
+    let (mut hits, mut files, mut total) = (Vec::new(), 0, 0);
+    let mut last_report = Instant::now();
+    for entry in walker {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let entry = match entry {
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => entry,
+            _ => continue,
+        };
+        files += 1;
+        // Unreadable files are skipped, like unreadable directories
+        let _ = search_file(entry.path(), &regex, &cancel, |hit| {
+            if total < MAX_HITS {
+                hits.push(hit);
+                total += 1;
+            }
+        });
+        if total >= MAX_HITS {
+            break;
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            if !send(std::mem::take(&mut hits), std::mem::take(&mut files), false) {
+                return;
+            }
+            last_report = Instant::now();
+        }
+    }
+    send(hits, files, true);
+}
+
+/// A content search and its results so far
+pub struct Search {
+    events: Sender<Event<Key>>,
+    cancel: Arc<AtomicBool>,
+    /// Counts searches, so batches from an abandoned one are recognized
+    search: usize,
+    pub root: PathBuf,
+    pub pattern: String,
+    pub options: SearchOptions,
+    pub hits: StatefulList<SearchHit>,
+    /// Files looked through so far
+    pub files: usize,
+    /// Still searching
+    pub running: bool,
+}
+
+impl Search {
+    /// Workers deliver their results through `events`.
+    pub fn new(events: Sender<Event<Key>>) -> Self {
+        Self {
+            events,
+            cancel: Arc::new(AtomicBool::new(false)),
+            search: 0,
+            root: PathBuf::new(),
+            pattern: String::new(),
+            options: SearchOptions::default(),
+            hits: StatefulList::with_items(vec![]),
+            files: 0,
+            running: false,
+        }
+    }
+
+    /// Start searching the files below `root` for `pattern`, dropping any earlier results.
+    pub fn start(&mut self, root: &Path, pattern: &str, options: SearchOptions) -> Result<()> {
+        let regex = options.compile(pattern)?;
+        self.stop();
+        self.cancel = Arc::new(AtomicBool::new(false));
+        self.search += 1;
+        self.root = root.to_path_buf();
+        self.pattern = pattern.to_string();
+        self.options = options;
+        self.hits = StatefulList::with_items(vec![]);
+        self.files = 0;
+        self.running = true;
+        let (root, search) = (self.root.clone(), self.search);
+        let (events, cancel) = (self.events.clone(), self.cancel.clone());
+        thread::spawn(move || search_tree(root, regex, search, events, cancel));
+        Ok(())
+    }
+
+    /// Stop searching, keeping what has been found.
+    pub fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.running = false;
+    }
+
+    /// Take a batch from the worker.  Returns whether the first hit just came in.
+    pub fn insert(&mut self, event: SearchEvent) -> bool {
+        if event.search != self.search {
+            return false;
+        }
+        if event.done {
+            self.running = false;
+        }
+        self.files += event.files;
+        self.hits.items.extend(event.hits);
+        if self.hits.state.selected().is_none() && !self.hits.items.is_empty() {
+            self.hits.state.select(Some(0));
+            return true;
+        }
+        false
+    }
+
+    /// Where a hit is, relative to where the search started
+    pub fn relative_path<'a>(&self, hit: &'a SearchHit) -> &'a Path {
+        hit.path.strip_prefix(&self.root).unwrap_or(&hit.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_search_file() {
+        let path = std::env::temp_dir().join(format!("file_utility_search_{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "first line\r\n    let Answer = 42;\nno\nanswer again",
+        )
+        .unwrap();
+        let search = |pattern, options: SearchOptions| {
+            let mut hits = Vec::new();
+            let regex = options.compile(pattern).unwrap();
+            search_file(&path, &regex, &AtomicBool::new(false), |hit| hits.push(hit)).unwrap();
+            hits.into_iter()
+                .map(|hit| (hit.line, hit.offset, hit.snippet, hit.matched))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search("answer", SearchOptions::default()),
+            vec![
+                (2, 12, "let Answer = 42;".to_string(), 4..10),
+                (4, 36, "answer again".to_string(), 0..6),
+            ]
+        );
+        let sensitive = SearchOptions {
+            regex: false,
+            case: CaseMode::Sensitive,
+        };
+        assert_eq!(search("answer", sensitive).len(), 1);
+        // Fixed strings don't treat `.` specially, regular expressions do
+        assert_eq!(search("e.", SearchOptions::default()).len(), 0);
+        let regex = SearchOptions {
+            regex: true,
+            case: CaseMode::Smart,
+        };
+        assert_eq!(search(r"\d+", regex).len(), 1);
+        assert!(SearchOptions::default().compile("").is_err());
+
+        // Only the start of an overlong line is searched, and the lines after it are still counted
+        let mut long = "x".repeat(MAX_LINE_BYTES as usize * 2);
+        long.push_str(" answer\nanswer\n");
+        std::fs::write(&path, &long).unwrap();
+        assert_eq!(
+            search("answer", SearchOptions::default()),
+            vec![(2, MAX_LINE_BYTES * 2 + 8, "answer".to_string(), 0..6)]
+        );
+
+        std::fs::write(&path, b"text\0binary answer").unwrap();
+        assert_eq!(search("answer", SearchOptions::default()), vec![]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
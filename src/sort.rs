@@ -0,0 +1,262 @@
+//! `sort` orders the entries of a directory listing.
+//!
+//! `fs::read_dir` hands entries back in whatever order the filesystem stores them, so listings are sorted by one of
+//! several keys, either way round, optionally with directories gathered at the top.
+
+use crate::app::FileListing;
+
+// Metadata for the sort keys
+use std::{cmp::Ordering, fs, os::unix::fs::FileTypeExt, path::Path, time::SystemTime};
+
+/// What entries are compared by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// Byte for byte, like `ls`
+    Name,
+    /// Numbers in names compare by value, so `file2` comes before `file10`
+    Natural,
+    /// Letters compare regardless of case
+    CaseInsensitive,
+    Size,
+    Modified,
+    Accessed,
+    Extension,
+    /// Directories, links, files, then special files
+    Type,
+}
+
+impl SortKey {
+    /// The next key, for cycling through them all
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Name => SortKey::Natural,
+            SortKey::Natural => SortKey::CaseInsensitive,
+            SortKey::CaseInsensitive => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Accessed,
+            SortKey::Accessed => SortKey::Extension,
+            SortKey::Extension => SortKey::Type,
+            SortKey::Type => SortKey::Name,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Natural => "natural name",
+            SortKey::CaseInsensitive => "name ignoring case",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Accessed => "accessed",
+            SortKey::Extension => "extension",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+/// How a listing is sorted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+    /// Directories come before everything else, whichever way the rest is sorted
+    pub dirs_first: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            key: SortKey::Natural,
+            descending: false,
+            dirs_first: true,
+        }
+    }
+}
+
+/// Compare names so that runs of digits compare as numbers: `a9` < `a10` < `b1`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_char, b_char) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) => (a_char, b_char),
+        };
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let a_digits = a[..a_end].trim_start_matches('0');
+            let b_digits = b[..b_end].trim_start_matches('0');
+            // Without leading zeros, a longer run of digits is a bigger number
+            let ordering = a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then_with(|| a_digits.cmp(b_digits));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            a = &a[a_end..];
+            b = &b[b_end..];
+        } else {
+            if a_char != b_char {
+                return a_char.cmp(&b_char);
+            }
+            a = &a[a_char.len_utf8()..];
+            b = &b[b_char.len_utf8()..];
+        }
+    }
+}
+
+/// Everything the sort keys need about one entry, read once per sort
+struct SortInfo {
+    name: String,
+    extension: String,
+    is_dir: bool,
+    type_rank: u8,
+    len: u64,
+    modified: Option<SystemTime>,
+    accessed: Option<SystemTime>,
+}
+
+impl SortInfo {
+    fn read(path: &Path, is_dir: bool) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let m = fs::symlink_metadata(path).ok();
+        let type_rank = match m.as_ref().map(|m| m.file_type()) {
+            _ if is_dir => 0,
+            Some(t) if t.is_symlink() => 1,
+            Some(t) if t.is_file() => 2,
+            Some(t) if t.is_fifo() || t.is_socket() => 3,
+            _ => 4,
+        };
+        Self {
+            name,
+            extension,
+            is_dir,
+            type_rank,
+            len: m.as_ref().map_or(0, |m| m.len()),
+            modified: m.as_ref().and_then(|m| m.modified().ok()),
+            accessed: m.as_ref().and_then(|m| m.accessed().ok()),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Describe the order for the listing's title, e.g. `natural name ↑, dirs first`
+    pub fn label(&self) -> String {
+        let mut label = format!(
+            "{} {}",
+            self.key.label(),
+            if self.descending {
+                "\u{2193}"
+            } else {
+                "\u{2191}"
+            }
+        );
+        if self.dirs_first {
+            label.push_str(", dirs first");
+        }
+        label
+    }
+
+    fn compare(&self, a: &SortInfo, b: &SortInfo) -> Ordering {
+        let by_key = match self.key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Natural => natural_cmp(&a.name, &b.name),
+            SortKey::CaseInsensitive => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Size => a.len.cmp(&b.len),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Accessed => a.accessed.cmp(&b.accessed),
+            SortKey::Extension => a.extension.cmp(&b.extension),
+            SortKey::Type => a.type_rank.cmp(&b.type_rank),
+        }
+        // Ties fall back on the name, so the order is always the same
+        .then_with(|| natural_cmp(&a.name, &b.name));
+        let by_key = if self.descending {
+            by_key.reverse()
+        } else {
+            by_key
+        };
+        if self.dirs_first {
+            b.is_dir.cmp(&a.is_dir).then(by_key)
+        } else {
+            by_key
+        }
+    }
+
+    /// Sort listing entries in place.
+    pub fn sort(&self, entries: &mut Vec<(FileListing, usize)>) {
+        let mut keyed: Vec<_> = entries
+            .drain(..)
+            .map(|entry| (SortInfo::read(entry.0.path(), entry.0.is_directory), entry))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| self.compare(a, b));
+        entries.extend(keyed.into_iter().map(|(_, entry)| entry));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["file10", "file2", "File1", "file02b", "file2a", "a", ""];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["", "File1", "a", "file2", "file2a", "file02b", "file10"]
+        );
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let dir = std::env::temp_dir().join(format!("file_utility_sort_{}", std::process::id()));
+        fs::create_dir_all(dir.join("zdir")).unwrap();
+        fs::write(dir.join("b.txt"), "123").unwrap();
+        fs::write(dir.join("A.rs"), "1").unwrap();
+        fs::write(dir.join("c.md"), "12").unwrap();
+        let names = |order: SortOrder| {
+            let mut entries: Vec<_> = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| (FileListing::read(entry.unwrap().path()), 0))
+                .collect();
+            order.sort(&mut entries);
+            entries
+                .iter()
+                .map(|(listing, _)| listing.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let order = SortOrder::default();
+        assert_eq!(names(order), vec!["zdir", "A.rs", "b.txt", "c.md"]);
+        let order = SortOrder {
+            key: SortKey::Size,
+            descending: true,
+            dirs_first: true,
+        };
+        assert_eq!(names(order), vec!["zdir", "b.txt", "c.md", "A.rs"]);
+        let order = SortOrder {
+            key: SortKey::Extension,
+            ..SortOrder::default()
+        };
+        assert_eq!(names(order), vec!["zdir", "c.md", "A.rs", "b.txt"]);
+        let order = SortOrder {
+            key: SortKey::CaseInsensitive,
+            dirs_first: false,
+            ..SortOrder::default()
+        };
+        assert_eq!(names(order), vec!["A.rs", "b.txt", "c.md", "zdir"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! Adapted from <https://github.com/fdehau/tui-rs/blob/master/examples/util/mod.rs>.
 //!
-//! I added the StatefulList::grab_selected() method, and otherwise made no modification.
+//! I added the StatefulList::grab_selected() method, and made `next`/`previous` safe on empty lists.
 
 use tui::widgets::ListState;
 
@@ -28,6 +28,9 @@ impl<T> StatefulList<T> {
     }
 
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -42,6 +45,9 @@ impl<T> StatefulList<T> {
     }
 
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
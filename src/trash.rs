@@ -0,0 +1,292 @@
+//! `trash` implements the home trash directory of the freedesktop.org Trash specification.
+//!
+//! See <https://specifications.freedesktop.org/trash-spec/trashspec-latest.html>.
+//!
+//! Trashed entries are moved into `Trash/files`, and a matching `Trash/info/<name>.trashinfo` file records
+//! where they came from and when they were deleted.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{anyhow, bail, Result};
+
+// DateTime handling
+use chrono::prelude::Local;
+
+use crate::fs_ops::{move_path, Progress};
+
+// Input and output, OS integration
+use std::{
+    env,
+    ffi::OsStr,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Suffix of every file in the `info` directory
+const INFO_EXTENSION: &str = ".trashinfo";
+
+/// Percent-encode a path the way the specification asks for: like a URL, but keeping the slashes.
+fn encode_path(path: &Path) -> String {
+    let mut result = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+/// Reverse `encode_path`.  Malformed escapes are kept as they are.
+fn decode_path(s: &str) -> PathBuf {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                result.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(OsStr::from_bytes(&result))
+}
+
+/// Pull the `Path` and `DeletionDate` keys out of a `.trashinfo` file.
+fn parse_info(contents: &str) -> Option<(PathBuf, String)> {
+    let mut lines = contents.lines();
+    if lines.next()?.trim() != "[Trash Info]" {
+        return None;
+    }
+    let mut path = None;
+    let mut date = String::new();
+    for line in lines {
+        if let Some(value) = line.strip_prefix("Path=") {
+            path = Some(decode_path(value.trim()));
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            date = value.trim().to_string();
+        }
+    }
+    path.map(|p| (p, date))
+}
+
+/// A single trashed item
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashEntry {
+    /// File name inside `Trash/files`
+    pub name: String,
+    /// Where the item lived before it was trashed
+    pub original_path: PathBuf,
+    /// Local time of deletion, as `YYYY-MM-DDThh:mm:ss`
+    pub deletion_date: String,
+}
+
+/// Handle to a trash directory
+pub struct Trash {
+    root: PathBuf,
+}
+
+impl Trash {
+    /// The user's home trash: `$XDG_DATA_HOME/Trash`, defaulting to `~/.local/share/Trash`.
+    pub fn home() -> Result<Self> {
+        let data_home = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                let home = env::var_os("HOME").ok_or_else(|| anyhow!("$HOME is not set"))?;
+                PathBuf::from(home).join(".local/share")
+            }
+        };
+        Ok(Self::at(data_home.join("Trash")))
+    }
+
+    /// A trash rooted at an arbitrary directory
+    pub fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn files_dir(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    fn info_dir(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    /// Where the trashed item itself is stored
+    pub fn file_path(&self, entry: &TrashEntry) -> PathBuf {
+        self.files_dir().join(&entry.name)
+    }
+
+    fn info_path(&self, name: &str) -> PathBuf {
+        self.info_dir().join(format!("{}{}", name, INFO_EXTENSION))
+    }
+
+    /// Move an absolute path into the trash.
+    pub fn put(&self, path: &Path, progress: &mut dyn Progress) -> Result<()> {
+        if !path.is_absolute() {
+            bail!("Cannot trash relative path {:?}", path);
+        }
+        let base = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Cannot trash {:?}", path))?
+            .to_string_lossy()
+            .into_owned();
+        fs::create_dir_all(self.files_dir())?;
+        fs::create_dir_all(self.info_dir())?;
+
+        // Creating the info file with O_EXCL reserves the name, as the specification requires
+        let mut counter = 1;
+        let (name, mut info) = loop {
+            let name = if counter == 1 {
+                base.clone()
+            } else {
+                format!("{}.{}", base, counter)
+            };
+            let info = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.info_path(&name));
+            match info {
+                // Not `exists`, which would take a dangling link already in the trash for a free name
+                Ok(f) if fs::symlink_metadata(self.files_dir().join(&name)).is_err() => {
+                    break (name, f)
+                }
+                Ok(_) => fs::remove_file(self.info_path(&name))?,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            counter += 1;
+        };
+
+        let written = write!(
+            info,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(path),
+            Local::now().format("%Y-%m-%dT%H:%M:%S")
+        );
+        let moved = written
+            .map_err(anyhow::Error::from)
+            .and_then(|_| move_path(path, &self.files_dir().join(&name), progress));
+        if moved.is_err() {
+            let _ = fs::remove_file(self.info_path(&name));
+        }
+        moved
+    }
+
+    /// Every entry with a readable `.trashinfo` file, most recently deleted first.
+    pub fn list(&self) -> Result<Vec<TrashEntry>> {
+        let mut result = Vec::new();
+        let entries = match fs::read_dir(self.info_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(result),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = match file_name.strip_suffix(INFO_EXTENSION) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let contents = match fs::read_to_string(entry.path()) {
+                Ok(contents) => contents,
+                Err(_) => continue,
+            };
+            if let Some((original_path, deletion_date)) = parse_info(&contents) {
+                result.push(TrashEntry {
+                    name,
+                    original_path,
+                    deletion_date,
+                });
+            }
+        }
+        result.sort_by(|a, b| b.deletion_date.cmp(&a.deletion_date));
+        Ok(result)
+    }
+
+    /// Move an entry back to where it came from.  Refuses to overwrite anything that has since taken its place.
+    pub fn restore(&self, entry: &TrashEntry, progress: &mut dyn Progress) -> Result<()> {
+        if fs::symlink_metadata(&entry.original_path).is_ok() {
+            bail!("{:?} already exists", entry.original_path);
+        }
+        if let Some(parent) = entry.original_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&self.file_path(entry), &entry.original_path, progress)?;
+        fs::remove_file(self.info_path(&entry.name))?;
+        Ok(())
+    }
+
+    /// Permanently delete an entry.
+    pub fn purge(&self, entry: &TrashEntry) -> Result<()> {
+        let path = self.file_path(entry);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.is_dir() => fs::remove_dir_all(&path)?,
+            Ok(_) => fs::remove_file(&path)?,
+            // Already gone; just drop the stale info file
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        fs::remove_file(self.info_path(&entry.name))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs_ops::NoProgress;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_roundtrip_path_encoding() {
+        let path = Path::new("/home/me/My Files/100%_ünïcode.txt");
+        let encoded = encode_path(path);
+        assert_eq!("/home/me/My%20Files/100%25_%C3%BCn%C3%AFcode.txt", encoded);
+        assert_eq!(path, decode_path(&encoded));
+    }
+
+    #[test]
+    fn test_put_restore_purge() {
+        let dir = env::temp_dir().join(format!("file_utility_trash_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let trash = Trash::at(dir.join("Trash"));
+        let victim = dir.join("victim");
+
+        // Trash the same name twice to exercise the collision handling
+        fs::write(&victim, "first").unwrap();
+        trash.put(&victim, &mut NoProgress).unwrap();
+        fs::write(&victim, "second").unwrap();
+        trash.put(&victim, &mut NoProgress).unwrap();
+        assert!(!victim.exists());
+
+        let entries = trash.list().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.original_path == victim));
+        let first = entries.iter().find(|e| e.name == "victim").unwrap();
+        let second = entries.iter().find(|e| e.name == "victim.2").unwrap();
+
+        trash.restore(first, &mut NoProgress).unwrap();
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "first");
+        // The original location is occupied again
+        assert!(trash.restore(second, &mut NoProgress).is_err());
+        trash.purge(second).unwrap();
+        assert!(trash.list().unwrap().is_empty());
+
+        // A dangling link left in the trash still takes up its name
+        fs::remove_file(&victim).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), trash.files_dir().join("victim")).unwrap();
+        fs::write(&victim, "third").unwrap();
+        trash.put(&victim, &mut NoProgress).unwrap();
+        assert_eq!(trash.list().unwrap()[0].name, "victim.2");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,15 @@
 //! This module imperatively defines the user interface.  It is computed every frame tick.
 
-use crate::app::App;
+use crate::{
+    app::{human_size, App, AppMode, FileListing, InputType},
+    jobs::{Job, JobState},
+    messages::{Level, Message},
+    permissions::{describe_mode, mode_warning, EDITOR_COLUMNS, EDITOR_ROWS},
+    preview::{hex_lines, Page, PreviewState},
+};
 
 // Convert a relative path to absolute.
-use std::fs::canonicalize;
+use std::{fs::canonicalize, time::Duration};
 
 // TUI widget library
 use tui::{
@@ -11,16 +17,22 @@ use tui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
     Frame,
 };
 use unicode_width::UnicodeWidthStr;
 
 // The usage text isn't dynamic in any way.
-const USAGE_TEXT: &str = "\u{1F815}/w: up \u{1F817}/s: down \u{1F816}/d: enter directory \u{1F814}/a: unselect all\nc: copy file j: jump to directory p: change permissions\nq: quit";
+const USAGE_TEXT: &str = "\u{1F815}/w: up \u{1F817}/s: down \u{1F816}/d: enter directory \u{1F814}/a: unselect all b/B: back/forward .: show hidden files /: filter\nCtrl-p: find files G: search contents T: search results ?: find by size, age, permissions, type or owner\nc: copy file m: move/rename x: trash j: jump to directory p: change permissions P: permission editor R: recursive permissions\nC: change owner O: change owner recursively A: toggle archive copy L: toggle following links on copy f: follow link F: go to link target's directory\nS: make symlink H: make hard link n: new file N: new directory o: cycle sort r: reverse sort z: directories first\nt: view trash k: cancel job space: mark *: mark all i: invert marks u: clear marks\nPgUp/PgDn: page preview J/K: scroll preview h: hex/text view g: go to offset #: line numbers W: wrap\nl: message log Esc: dismiss message, then leave query results q: quit";
+const LOG_USAGE_TEXT: &str =
+    "\u{1F815}/w: scroll up \u{1F817}/s: scroll down\nEsc/l: back to directory q: quit";
+const FINDER_USAGE_TEXT: &str = "Type to search \u{1F815}\u{1F817}: choose Enter: go to file\nTab: stay on this filesystem or cross into others Ctrl-g: skip or include ignored files Esc: close";
+const SEARCH_USAGE_TEXT: &str = "\u{1F815}/w: up \u{1F817}/s: down Enter: go to file G: new search k: stop searching\nPgUp/PgDn: page preview J/K: scroll preview #: line numbers W: wrap Esc/T: back to directory q: quit";
+const EDITOR_USAGE_TEXT: &str = "\u{1F815}\u{1F817}\u{1F814}\u{1F816}/wasd: move space: toggle\nEnter: apply Esc: cancel q: quit";
+const TRASH_USAGE_TEXT: &str = "\u{1F815}/w: up \u{1F817}/s: down\nr: restore X: delete permanently\nEsc/t: back to directory q: quit";
 
 /// Helper function to build a block
-fn create_block(title: &str) -> Block {
+fn create_block(title: &str) -> Block<'_> {
     Block::default()
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::Black).fg(Color::White))
@@ -38,22 +50,335 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
         .split(f.size());
 
-    draw_dir_list(f, app, chunks[0]);
+    if app.mode == AppMode::Trash {
+        draw_trash_list(f, app, chunks[0]);
+    } else if app.mode == AppMode::Search {
+        draw_search_list(f, app, chunks[0]);
+    } else {
+        draw_dir_list(f, app, chunks[0]);
+    }
     draw_left_panel(f, app, chunks[1]);
+    if app.mode == AppMode::PermissionEditor {
+        draw_permission_editor(f, app);
+    }
+    if app.mode == AppMode::Finder {
+        draw_finder(f, app);
+    }
+}
+
+/// A rectangle of the given size in the middle of `area`, shrunk to fit
+fn centered(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Render the permission editor as a dialog over everything else.
+fn draw_permission_editor<B>(f: &mut Frame<B>, app: &mut App)
+where
+    B: Backend,
+{
+    let editor = &app.permission_editor;
+    let mut header = vec![Span::raw(format!("{:<8}", ""))];
+    header.extend(
+        EDITOR_COLUMNS
+            .iter()
+            .map(|label| Span::raw(format!("{:<9}", label))),
+    );
+    let mut lines = vec![Spans::from(header)];
+    for (row, label) in EDITOR_ROWS.iter().enumerate() {
+        let mut spans = vec![Span::raw(format!("{:<8}", label))];
+        for col in 0..EDITOR_COLUMNS.len() {
+            let check = if editor.is_set(row, col) {
+                "[x]"
+            } else {
+                "[ ]"
+            };
+            let style = if (row, col) == (editor.row, editor.col) {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            spans.push(Span::styled(check, style));
+            spans.push(Span::raw("      "));
+        }
+        lines.push(Spans::from(spans));
+    }
+    lines.push(Spans::from(""));
+    lines.push(Spans::from(Span::styled(
+        describe_mode(editor.mode),
+        Style::default().add_modifier(Modifier::BOLD),
+    )));
+    if let Some(warning) = mode_warning(editor.mode, editor.is_dir) {
+        lines.push(Spans::from(Span::styled(
+            format!("Warning: {}", warning),
+            Style::default().fg(Color::Red),
+        )));
+    }
+
+    let title = match app.targets().len() {
+        1 => "Permissions".to_string(),
+        n => format!("Permissions of {} entries", n),
+    };
+    let area = centered(48, 12, f.size());
+    let dialog = Paragraph::new(lines)
+        // Trimming would strip the indent that lines the column labels up with the checkboxes
+        .wrap(Wrap { trim: false })
+        .block(create_block(&title));
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+/// Render the fuzzy finder as a dialog over everything else: the query on top, ranked paths below.
+fn draw_finder<B>(f: &mut Frame<B>, app: &mut App)
+where
+    B: Backend,
+{
+    let size = f.size();
+    let area = centered(size.width * 4 / 5, size.height * 4 / 5, size);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(area);
+    f.render_widget(Clear, area);
+
+    let finder = &app.finder;
+    let mut title = format!("Find below {}", finder.root.display());
+    if !finder.options.gitignore {
+        title.push_str(" (including ignored files)");
+    }
+    if !finder.options.one_file_system {
+        title.push_str(" (crossing filesystems)");
+    }
+    let query = Paragraph::new(finder.query.as_str())
+        .style(Style::default().fg(Color::Yellow))
+        .block(create_block(&title));
+    f.render_widget(query, chunks[0]);
+    f.set_cursor(
+        chunks[0].x + finder.query.width() as u16 + 1,
+        chunks[0].y + 1,
+    );
+
+    // Matched characters stand out
+    let items: Vec<ListItem> = finder
+        .results
+        .iter()
+        .map(|result| {
+            let path = finder.relative_path(result).to_string_lossy();
+            let spans: Vec<Span> = path
+                .chars()
+                .enumerate()
+                .map(|(idx, c)| {
+                    if result.positions.contains(&idx) {
+                        Span::styled(
+                            c.to_string(),
+                            Style::default()
+                                .fg(Color::Blue)
+                                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                        )
+                    } else {
+                        Span::raw(c.to_string())
+                    }
+                })
+                .collect();
+            ListItem::new(Spans::from(spans))
+        })
+        .collect();
+    let status = format!(
+        "{} of {} found{}",
+        finder.results.len(),
+        finder.found(),
+        if finder.walking { ", searching..." } else { "" }
+    );
+    let results = List::new(items)
+        .block(create_block(&status))
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">> ");
+    f.render_stateful_widget(results, chunks[1], &mut app.finder.state);
 }
 
-///
+/// Render the right-hand column: details on top, usage below.
 fn draw_left_panel<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
+    // The jobs panel only takes up room while there are jobs to show
+    let jobs_height = if app.jobs.list.is_empty() {
+        0
+    } else {
+        app.jobs.list.len().min(6) as u16 + 2
+    };
+    // Likewise the notification, which is hidden once dismissed
+    let notification_height = app
+        .messages
+        .current()
+        .map_or(0, |m| (m.causes.len() as u16 + 1).min(4) + 2);
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(0), Constraint::Length(5)].as_ref())
+        .constraints(
+            [
+                Constraint::Min(0),
+                Constraint::Length(jobs_height),
+                Constraint::Length(notification_height),
+                Constraint::Length(10),
+            ]
+            .as_ref(),
+        )
         .split(area);
 
-    draw_details(f, app, chunks[0]);
-    draw_usage(f, app, chunks[1]);
+    if app.mode == AppMode::Log {
+        draw_log(f, app, chunks[0]);
+    } else {
+        draw_details(f, app, chunks[0]);
+    }
+    if jobs_height > 0 {
+        draw_jobs(f, app, chunks[1]);
+    }
+    if notification_height > 0 {
+        draw_notification(f, app, chunks[2]);
+    }
+    draw_usage(f, app, chunks[3]);
+}
+
+/// The colour messages of each level are shown in
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Info => Color::Green,
+        Level::Warning => Color::Yellow,
+        Level::Error => Color::Red,
+    }
+}
+
+/// The lines of one message: the text itself, then each of its causes
+fn message_lines(message: &Message) -> Vec<Spans<'_>> {
+    let style = Style::default().fg(level_color(message.level));
+    let mut lines = vec![Spans::from(Span::styled(message.text.as_str(), style))];
+    for cause in &message.causes {
+        lines.push(Spans::from(Span::styled(
+            format!("  caused by: {}", cause),
+            style,
+        )));
+    }
+    lines
+}
+
+/// Render the latest message, until it is dismissed.
+fn draw_notification<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    if let Some(message) = app.messages.current() {
+        let title = format!("{} (Esc: dismiss, l: log)", message.level.label());
+        let notification = Paragraph::new(message_lines(message))
+            .wrap(Wrap { trim: false })
+            .block(create_block(&title));
+        f.render_widget(notification, area);
+    }
+}
+
+/// Render every message this session, oldest first, in place of the details pane.
+fn draw_log<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let mut lines = vec![];
+    for message in &app.messages.entries {
+        lines.push(Spans::from(Span::styled(
+            format!(
+                "{} {}",
+                message.time.format("%H:%M:%S"),
+                message.level.label()
+            ),
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        lines.extend(message_lines(message));
+    }
+    if lines.is_empty() {
+        lines.push(Spans::from("No messages."));
+    }
+    let log = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((app.messages.scroll, 0))
+        .block(create_block("Message log"));
+    f.render_widget(log, area);
+}
+
+/// Format a duration as e.g. `1h02m03s`, `2m05s` or `7s`
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+/// One line of the jobs panel
+fn job_line(job: &Job) -> Spans<'_> {
+    let mut spans = vec![Span::styled(
+        format!("{} ", job.description),
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+    let status = match &job.state {
+        // Without totals, all there is to show is how far the job has got
+        JobState::Running if !job.measured => Span::from(format!(
+            "{}, {} entries, {}/s",
+            human_size(job.bytes_done),
+            job.entries_done,
+            human_size(job.throughput() as u64),
+        )),
+        JobState::Running => {
+            let percent = (job.bytes_done * 100)
+                .checked_div(job.bytes_total)
+                .unwrap_or(0);
+            let mut status = format!(
+                "{}% {} / {}, {} / {} entries, {}/s",
+                percent,
+                human_size(job.bytes_done),
+                human_size(job.bytes_total),
+                job.entries_done,
+                job.entries_total,
+                human_size(job.throughput() as u64),
+            );
+            if let Some(eta) = job.eta() {
+                status.push_str(&format!(", ETA {}", format_duration(eta)));
+            }
+            Span::from(status)
+        }
+        JobState::Done => Span::styled("done", Style::default().fg(Color::Green)),
+        JobState::Cancelled => Span::styled("cancelled", Style::default().fg(Color::Yellow)),
+        JobState::Failed { error, .. } => Span::styled(
+            format!("failed: {}", error),
+            Style::default().fg(Color::Red),
+        ),
+    };
+    spans.push(status);
+    Spans::from(spans)
+}
+
+/// Render the background jobs panel, newest first.
+fn draw_jobs<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let lines: Vec<Spans> = app.jobs.list.iter().rev().map(job_line).collect();
+    let jobs = Paragraph::new(lines)
+        .style(Style::default())
+        .block(create_block("Jobs"));
+    f.render_widget(jobs, area);
 }
 
 /// Render the selectable directory listing pane.
@@ -61,19 +386,57 @@ fn draw_dir_list<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
-    // Add text to each result that's a directory
+    // Add text to each result that's a directory, and flag marked entries
     let items: Vec<ListItem> = app
         .dir_list
         .items
         .iter()
         .map(|i| {
+            let marked = app.is_marked(&i.0);
             let mut spans = vec![];
+            if marked {
+                spans.push(Span::styled(
+                    "* ",
+                    Style::default().add_modifier(Modifier::BOLD),
+                ));
+            }
             if i.1 == 0 {
                 spans.push(Span::from("."));
             } else if i.1 == 1 {
                 spans.push(Span::from(".."));
             } else {
-                spans.push(Span::from(i.0.to_string()));
+                let name = i.0.to_string();
+                // Query matches come from all over the tree, so show where
+                let parent = app.query.as_ref().and_then(|query| {
+                    let parent = i.0.path().parent()?.strip_prefix(&query.root).ok()?;
+                    Some(parent).filter(|parent| !parent.as_os_str().is_empty())
+                });
+                if let Some(parent) = parent {
+                    spans.push(Span::from(format!("{}/", parent.display())));
+                }
+                // Pick out the part of the name the filter matched
+                match app.filter.as_ref().and_then(|filter| filter.find(&name)) {
+                    Some(range) if !range.is_empty() => {
+                        spans.push(Span::from(name[..range.start].to_string()));
+                        spans.push(Span::styled(
+                            name[range.clone()].to_string(),
+                            Style::default()
+                                .fg(Color::Blue)
+                                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                        ));
+                        spans.push(Span::from(name[range.end..].to_string()));
+                    }
+                    _ => spans.push(Span::from(name)),
+                }
+                if let Some(link) = &i.0.link {
+                    spans.push(Span::from(format!(" -> {}", link.target.display())));
+                    if let Some(problem) = link.problem() {
+                        spans.push(Span::styled(
+                            format!(" ({})", problem),
+                            Style::default().fg(Color::Red),
+                        ));
+                    }
+                }
                 if i.0.is_directory {
                     spans.push(Span::styled(
                         " - directory",
@@ -82,19 +445,47 @@ where
                 }
             }
             // Push the full text to the list
+            let background = if marked { Color::Yellow } else { Color::White };
             ListItem::new(Spans::from(spans))
-                .style(Style::default().fg(Color::Black).bg(Color::White))
+                .style(Style::default().fg(Color::Black).bg(background))
         })
         .collect();
 
-    // The block title will show the current directory
-    let absolute = canonicalize(&app.current_directory)
-        .expect("Could not get absolute path from relative path");
-    let listing_title = &absolute.to_str().unwrap_or("\"???\"")[..absolute.to_str().unwrap().len()];
+    // The block title will show the current directory, how it's sorted, and how many entries are marked.  Query
+    // results show the query instead.
+    let mut listing_title = match &app.query {
+        Some(query) => format!(
+            "Query {:?} in {}: {} matches{}",
+            query.text,
+            query.root.display(),
+            query.matches.len(),
+            if query.running { ", searching..." } else { "" }
+        ),
+        None => {
+            let absolute = canonicalize(&app.current_directory)
+                .unwrap_or_else(|_| app.current_directory.clone());
+            let mut title = absolute.to_str().unwrap_or("\"???\"").to_string();
+            title.push_str(&format!(" [sort: {}]", app.sort_order().label()));
+            title
+        }
+    };
+    if let Some(filter) = &app.filter {
+        listing_title.push_str(&format!(" [filter: {}]", filter.label()));
+    }
+    if app.show_hidden {
+        listing_title.push_str(" [hidden shown]");
+    }
+    if !app.marked.is_empty() {
+        listing_title.push_str(&format!(" ({} marked", app.marked.len()));
+        match app.marked_out_of_view() {
+            0 => listing_title.push(')'),
+            hidden => listing_title.push_str(&format!(", {} out of view)", hidden)),
+        }
+    }
 
     // Create a List from all items, highlight the selected one
     let items = List::new(items)
-        .block(create_block(listing_title))
+        .block(create_block(&listing_title))
         .highlight_style(
             Style::default()
                 .bg(Color::LightGreen)
@@ -106,34 +497,240 @@ where
     f.render_stateful_widget(items, area, &mut app.dir_list.state);
 }
 
+/// Render the contents of the trash in place of the directory listing.
+fn draw_trash_list<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let items: Vec<ListItem> = app
+        .trash_list
+        .items
+        .iter()
+        .map(|entry| {
+            ListItem::new(Spans::from(vec![
+                Span::from(entry.original_path.to_string_lossy().into_owned()),
+                Span::styled(
+                    format!(" - {}", entry.deletion_date),
+                    Style::default().add_modifier(Modifier::ITALIC),
+                ),
+            ]))
+            .style(Style::default().fg(Color::Black).bg(Color::White))
+        })
+        .collect();
+
+    let title = format!("Trash ({} items)", app.trash_list.items.len());
+    let items = List::new(items)
+        .block(create_block(&title))
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">> ");
+
+    f.render_stateful_widget(items, area, &mut app.trash_list.state);
+}
+
+/// Render content search results in place of the directory listing, as `path:line: snippet`.
+fn draw_search_list<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let search = &app.search;
+    let items: Vec<ListItem> = search
+        .hits
+        .items
+        .iter()
+        .map(|hit| {
+            let snippet = &hit.snippet;
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{}:{}: ", search.relative_path(hit).display(), hit.line),
+                    Style::default().add_modifier(Modifier::ITALIC),
+                ),
+                Span::from(snippet[..hit.matched.start].to_string()),
+                Span::styled(
+                    snippet[hit.matched.clone()].to_string(),
+                    Style::default()
+                        .fg(Color::Blue)
+                        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                ),
+                Span::from(snippet[hit.matched.end..].to_string()),
+            ]))
+            .style(Style::default().fg(Color::Black).bg(Color::White))
+        })
+        .collect();
+
+    let title = format!(
+        "{:?} ({}) in {}: {} lines in {} files searched{}",
+        search.pattern,
+        search.options.label(),
+        search.root.display(),
+        search.hits.items.len(),
+        search.files,
+        if search.running { ", searching..." } else { "" }
+    );
+    let items = List::new(items)
+        .block(create_block(&title))
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">> ");
+
+    f.render_stateful_widget(items, area, &mut app.search.hits.state);
+}
+
 /// Render the details pane.
 fn draw_details<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
     // The next widget is the details block, which displays information about the highlighted entry.
-    let detail_text = if let Some(listing) = app.dir_list.grab_selected() {
-        let detail = listing
-            .0
-            .detail_string()
-            .unwrap_or_else(|_| "Could not read metadata".to_string());
-
-        // It also grabs the contents
-        let contents = if let Some(s) = listing.0.contents().unwrap_or(None) {
-            format!("File Contents:\n{}", s)
+    let detail_text = if app.mode == AppMode::Trash {
+        if let Some(entry) = app.trash_list.grab_selected() {
+            format!(
+                "Originally at {:?}.\nDeleted: {}\nTrash name: {}",
+                entry.original_path, entry.deletion_date, entry.name
+            )
         } else {
-            "".to_string()
-        };
-
-        format!("{}\n\n{}", detail, contents)
+            "Nothing selected.".to_string()
+        }
+    } else if app.mode == AppMode::Search {
+        match app.search.hits.grab_selected() {
+            Some(hit) => format!(
+                "Line {} of {}\n{}",
+                hit.line,
+                hit.path.display(),
+                FileListing::read(hit.path.clone())
+                    .detail_string(&app.users)
+                    .unwrap_or_else(|_| "Could not read metadata".to_string())
+            ),
+            None => "Nothing selected.".to_string(),
+        }
+    } else if let Some(listing) = app.dir_list.grab_selected() {
+        listing
+            .0
+            .detail_string(&app.users)
+            .unwrap_or_else(|_| "Could not read metadata".to_string())
     } else {
         "Nothing selected.".to_string()
     };
 
+    // Trashed entries don't get a preview
+    if app.mode == AppMode::Trash {
+        let detail = Paragraph::new(detail_text)
+            .style(Style::default())
+            .block(create_block("Detail"));
+        f.render_widget(detail, area);
+        return;
+    }
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(detail_text.lines().count() as u16 + 2),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(area);
     let detail = Paragraph::new(detail_text)
         .style(Style::default())
         .block(create_block("Detail"));
-    f.render_widget(detail, area);
+    f.render_widget(detail, chunks[0]);
+
+    draw_preview(f, app, chunks[1]);
+}
+
+/// Render the current page of the selected file's contents.
+fn draw_preview<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let view = app.preview.view;
+    let (line_numbers, wrap) = (app.preview.line_numbers, app.preview.wrap);
+    let (text, range, hex) = match app.preview_selected() {
+        PreviewState::Unavailable(reason) => (Text::from(reason), None, false),
+        PreviewState::Loading => (Text::from("Loading..."), None, false),
+        PreviewState::Failed(e) => (
+            Text::from(format!("Could not read contents: {}", e)),
+            None,
+            false,
+        ),
+        PreviewState::Ready(page) => {
+            let hex = view.shows_hex(page);
+            let text = if hex {
+                Text::from(hex_lines(page.offset, &page.bytes).join("\n"))
+            } else {
+                Text::from(text_lines(page, line_numbers))
+            };
+            let range = (page.offset, page.offset + page.bytes.len() as u64);
+            (text, Some(range), hex)
+        }
+    };
+    let title = match range {
+        Some((start, end)) => format!(
+            "{}: bytes {}-{} of {} (h: {} view, g: go to offset)",
+            if hex { "Hex" } else { "Contents" },
+            start,
+            end,
+            app.preview.len,
+            view.next().label()
+        ),
+        None => "Contents".to_string(),
+    };
+    let mut preview = Paragraph::new(text)
+        .style(Style::default())
+        .scroll((app.preview.scroll, 0))
+        .block(create_block(&title));
+    if wrap && !hex {
+        preview = preview.wrap(Wrap { trim: false });
+    }
+    f.render_widget(preview, area);
+}
+
+/// A text page as lines, with its line numbers in the gutter if asked for.  Until the page's place in the file is
+/// known, lines are numbered from the top of the page with a `+`.
+fn text_lines(page: &Page, line_numbers: bool) -> Vec<Spans<'static>> {
+    // Binary pages are only styled when the text view is forced
+    let lines = if page.is_binary {
+        String::from_utf8_lossy(&page.bytes)
+            .lines()
+            .map(|line| Spans::from(line.to_string()))
+            .collect()
+    } else {
+        page.lines.clone()
+    };
+    if !line_numbers {
+        return lines;
+    }
+    let (first, prefix) = match page.first_line {
+        Some(first_line) => (first_line, ""),
+        None => (1, "+"),
+    };
+    let last = first + lines.len() as u64;
+    let width = prefix.len() + last.to_string().len();
+    lines
+        .into_iter()
+        .zip(first..)
+        .map(|(mut line, number)| {
+            line.0.insert(
+                0,
+                Span::styled(
+                    format!(
+                        "{:>width$} ",
+                        format!("{}{}", prefix, number),
+                        width = width
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            );
+            line
+        })
+        .collect()
 }
 
 /// Render the usage panel.
@@ -141,19 +738,70 @@ fn draw_usage<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
-    use crate::app::AppMode;
     match &app.mode {
-        AppMode::Nav => {
+        AppMode::Nav
+        | AppMode::Trash
+        | AppMode::Log
+        | AppMode::PermissionEditor
+        | AppMode::Finder
+        | AppMode::Search => {
             // Finally, on the bottom, we want to render usage instructions
-            let usage = Paragraph::new(Text::from(USAGE_TEXT))
+            let text = match app.mode {
+                AppMode::Trash => TRASH_USAGE_TEXT,
+                AppMode::Log => LOG_USAGE_TEXT,
+                AppMode::PermissionEditor => EDITOR_USAGE_TEXT,
+                AppMode::Finder => FINDER_USAGE_TEXT,
+                AppMode::Search => SEARCH_USAGE_TEXT,
+                _ => USAGE_TEXT,
+            };
+            let usage = Paragraph::new(Text::from(text))
                 .style(Style::default())
                 .block(create_block("Usage"));
             f.render_widget(usage, area);
         }
         AppMode::Input(input_type) => {
-            let input = Paragraph::new(app.user_input.as_ref())
+            let mut title = input_type.message().to_string();
+            if *input_type == InputType::CopyFile {
+                if app.copy_options.is_archive() {
+                    title.push_str(" (archive: preserving mode, times, owner and attributes)");
+                }
+                if app.copy_options.dereference {
+                    title.push_str(" (copying what links point to)");
+                }
+            }
+            let mut lines = vec![Spans::from(app.user_input.as_str())];
+            // Show what the input so far would do, or why it can't be used
+            let preview = match input_type {
+                _ if app.user_input.is_empty() => None,
+                InputType::Permission | InputType::RecursivePermission => {
+                    Some(app.permission_preview())
+                }
+                InputType::Ownership { .. } => Some(app.ownership_preview()),
+                InputType::Filter { kind } => Some(app.filter_preview(*kind)),
+                InputType::Query => Some(
+                    app.validate_input(InputType::Query)
+                        .map(|_| "Matches replace the listing; Esc goes back".to_string()),
+                ),
+                InputType::Search(options) => Some(
+                    options
+                        .compile(&app.user_input)
+                        .map(|_| format!("Searching as a {}", options.label())),
+                ),
+                _ => None,
+            };
+            if let Some(preview) = preview {
+                lines.push(match preview {
+                    Ok(preview) => {
+                        Spans::from(Span::styled(preview, Style::default().fg(Color::Green)))
+                    }
+                    Err(e) => {
+                        Spans::from(Span::styled(e.to_string(), Style::default().fg(Color::Red)))
+                    }
+                });
+            }
+            let input = Paragraph::new(lines)
                 .style(Style::default().fg(Color::Yellow))
-                .block(create_block(input_type.message()));
+                .block(create_block(&title));
             f.render_widget(input, area);
             f.set_cursor(area.x + app.user_input.width() as u16 + 1, area.y + 1);
         }
@@ -0,0 +1,282 @@
+//! `users` resolves user and group names, and changes who owns files.
+//!
+//! Names come straight from `/etc/passwd` and `/etc/group` rather than through NSS, which the static musl build
+//! doesn't have.  Users and groups only known to LDAP and the like can still be given by number.
+
+// Ergonomic Result and Error types to simply error handling boilerplate
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    fs_ops::{check_cancelled, Progress},
+    permissions::{entry_walked, ChangeSummary},
+};
+
+// Reading the account databases and walking directory trees
+use std::{
+    fs,
+    os::unix::fs::{lchown, MetadataExt},
+    path::Path,
+};
+
+/// Read a colon-separated account database into its lines' fields, skipping comments and blank lines.
+fn records(contents: &str) -> impl Iterator<Item = Vec<&str>> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').collect())
+}
+
+/// A user from `/etc/passwd`
+#[derive(Debug, Clone, PartialEq)]
+struct User {
+    name: String,
+    uid: u32,
+    /// The login group
+    gid: u32,
+}
+
+/// Parse `name:password:uid:gid:gecos:home:shell` lines.  Malformed lines are skipped.
+fn parse_passwd(contents: &str) -> Vec<User> {
+    records(contents)
+        .filter_map(|fields| {
+            Some(User {
+                name: fields.first()?.to_string(),
+                uid: fields.get(2)?.parse().ok()?,
+                gid: fields.get(3)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Parse `name:password:gid:members` lines into names and IDs.  Malformed lines are skipped.
+fn parse_group(contents: &str) -> Vec<(String, u32)> {
+    records(contents)
+        .filter_map(|fields| Some((fields.first()?.to_string(), fields.get(2)?.parse().ok()?)))
+        .collect()
+}
+
+/// The system's users and groups
+#[derive(Debug, Default)]
+pub struct Users {
+    users: Vec<User>,
+    groups: Vec<(String, u32)>,
+}
+
+impl Users {
+    /// Read `/etc/passwd` and `/etc/group`.  Missing files just mean there are no names to show.
+    pub fn load() -> Self {
+        let read = |path| fs::read_to_string(path).unwrap_or_default();
+        Self {
+            users: parse_passwd(&read("/etc/passwd")),
+            groups: parse_group(&read("/etc/group")),
+        }
+    }
+
+    pub fn user_name(&self, uid: u32) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|user| user.uid == uid)
+            .map(|user| user.name.as_str())
+    }
+
+    pub fn group_name(&self, gid: u32) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(_, id)| *id == gid)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// `name (uid)`, or just the number for unknown users
+    pub fn describe_user(&self, uid: u32) -> String {
+        match self.user_name(uid) {
+            Some(name) => format!("{} ({})", name, uid),
+            None => uid.to_string(),
+        }
+    }
+
+    /// `name (gid)`, or just the number for unknown groups
+    pub fn describe_group(&self, gid: u32) -> String {
+        match self.group_name(gid) {
+            Some(name) => format!("{} ({})", name, gid),
+            None => gid.to_string(),
+        }
+    }
+
+    fn user(&self, s: &str) -> Result<&User> {
+        self.users
+            .iter()
+            .find(|user| user.name == s)
+            .ok_or_else(|| anyhow!("Unknown user {:?}", s))
+    }
+
+    /// Look up a user ID by name, or take it as a number
+    pub fn uid(&self, s: &str) -> Result<u32> {
+        match s.parse() {
+            Ok(uid) => Ok(uid),
+            Err(_) => Ok(self.user(s)?.uid),
+        }
+    }
+
+    /// Look up a group ID by name, or take it as a number
+    pub fn gid(&self, s: &str) -> Result<u32> {
+        match s.parse() {
+            Ok(gid) => Ok(gid),
+            Err(_) => self
+                .groups
+                .iter()
+                .find(|(name, _)| name == s)
+                .map(|&(_, gid)| gid)
+                .ok_or_else(|| anyhow!("Unknown group {:?}", s)),
+        }
+    }
+
+    /// Parse an owner as `chown` takes it: `user`, `user:group`, `user:` for the user's login group, or `:group`.
+    /// Names and numeric IDs both work.
+    pub fn parse_owner(&self, s: &str) -> Result<Owner> {
+        let s = s.trim();
+        if s.is_empty() || s == ":" {
+            bail!("Give a user, a group, or both");
+        }
+        let owner = match s.split_once(':') {
+            None => Owner {
+                uid: Some(self.uid(s)?),
+                gid: None,
+            },
+            Some(("", group)) => Owner {
+                uid: None,
+                gid: Some(self.gid(group)?),
+            },
+            Some((user, "")) => {
+                let user = self.user(user)?;
+                Owner {
+                    uid: Some(user.uid),
+                    gid: Some(user.gid),
+                }
+            }
+            Some((user, group)) => Owner {
+                uid: Some(self.uid(user)?),
+                gid: Some(self.gid(group)?),
+            },
+        };
+        Ok(owner)
+    }
+}
+
+/// A new owner and group for files.  `None` leaves that part alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    /// Give `path` to the new owner and, if `recursive`, everything below it.  Symbolic links themselves are
+    /// changed, never what they point to.  Failures are collected in `summary`; only cancelling stops the walk with
+    /// an error.
+    pub fn apply(
+        &self,
+        path: &Path,
+        recursive: bool,
+        summary: &mut ChangeSummary,
+        progress: &mut dyn Progress,
+    ) -> Result<()> {
+        check_cancelled(progress)?;
+        let m = match fs::symlink_metadata(path) {
+            Ok(m) => m,
+            Err(e) => {
+                summary.failed.push(format!("{}: {}", path.display(), e));
+                return Ok(());
+            }
+        };
+        entry_walked(&m, progress);
+        let uid = self.uid.unwrap_or_else(|| m.uid());
+        let gid = self.gid.unwrap_or_else(|| m.gid());
+        if (uid, gid) == (m.uid(), m.gid()) {
+            summary.unchanged += 1;
+        } else {
+            match lchown(path, self.uid, self.gid) {
+                Ok(()) => summary.changed += 1,
+                Err(e) => summary.failed.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+
+        if recursive && m.is_dir() {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    summary.failed.push(format!("{}: {}", path.display(), e));
+                    return Ok(());
+                }
+            };
+            for entry in entries {
+                match entry {
+                    Ok(entry) => self.apply(&entry.path(), recursive, summary, progress)?,
+                    Err(e) => summary.failed.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn users() -> Users {
+        Users {
+            users: parse_passwd(
+                "# comment\nroot:x:0:0:root:/root:/bin/sh\nben:x:1000:100:Ben,,,:/home/ben:/bin/bash\nbroken:x:nope\n",
+            ),
+            groups: parse_group("root:x:0:\nusers:x:100:ben\nwheel:x:10:root,ben\n"),
+        }
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        let users = users();
+        let owner = |s| users.parse_owner(s).unwrap();
+        assert_eq!(
+            owner("ben"),
+            Owner {
+                uid: Some(1000),
+                gid: None
+            }
+        );
+        assert_eq!(
+            owner("ben:wheel"),
+            Owner {
+                uid: Some(1000),
+                gid: Some(10)
+            }
+        );
+        // The login group comes from the passwd entry
+        assert_eq!(
+            owner("ben:"),
+            Owner {
+                uid: Some(1000),
+                gid: Some(100)
+            }
+        );
+        assert_eq!(
+            owner(":0"),
+            Owner {
+                uid: None,
+                gid: Some(0)
+            }
+        );
+        assert_eq!(
+            owner("4242:4343"),
+            Owner {
+                uid: Some(4242),
+                gid: Some(4343)
+            }
+        );
+        for bad in &["", ":", "nobody", "ben:staff", "broken"] {
+            assert!(users.parse_owner(bad).is_err(), "{:?} parsed", bad);
+        }
+        assert_eq!(users.describe_user(1000), "ben (1000)");
+        assert_eq!(users.describe_group(7), "7");
+    }
+}